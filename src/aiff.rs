@@ -0,0 +1,294 @@
+//! A reader of AIFF and uncompressed AIFF-C files.
+//!
+//! Like `wav`, the samples are decoded into `f32` values normalized into
+//! `-1..1`, one `Vec` per channel.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use misc::*;
+use pcm::{invalid, read_body, read_frames, read_or_eof, skip, Endianness, SampleFormat};
+
+/// A marker in the `MARK` chunk.
+#[derive(Clone, Debug)]
+pub struct Mark {
+    pub id: u16,
+    pub name: String,
+    /// The position of the marker, which is an empty range.
+    pub position: TimeRange,
+}
+
+/// The contents of an AIFF or AIFF-C file.
+pub struct Aiff {
+    pub sample_rate: f64,
+    /// The format the samples were stored in.
+    pub format: SampleFormat,
+    /// The samples of each channel.
+    pub channels: Vec<Vec<f32>>,
+    pub marks: Vec<Mark>,
+}
+
+/// The contents of the `COMM` chunk that are needed to read the samples.
+struct Comm {
+    nb_frames: u64,
+    format: SampleFormat,
+    endianness: Endianness,
+}
+
+impl Aiff {
+    /// Reads an AIFF or AIFF-C file from `reader`.
+    ///
+    /// It will raise an error if the data is not an AIFF file with 1 to 32-bit
+    /// integer samples, or an AIFF-C file with the compression type `NONE`,
+    /// `twos`, `sowt` (little-endian), `fl32` or `fl64`,
+    /// or reading from `reader` fails.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Box<Error>> {
+        let mut form = [0u8; 12];
+        reader.read_exact(&mut form)?;
+        if &form[0..4] != b"FORM" {
+            return Err(invalid("not an IFF file"));
+        }
+        let aifc = match &form[8..12] {
+            b"AIFF" => false,
+            b"AIFC" => true,
+            _ => return Err(invalid("not an AIFF file")),
+        };
+
+        let mut aiff = Aiff {
+            sample_rate: 0f64,
+            format: SampleFormat::I16,
+            channels: Vec::new(),
+            marks: Vec::new(),
+        };
+        let mut comm: Option<Comm> = None;
+
+        loop {
+            let mut header = [0u8; 8];
+            if !read_or_eof(reader, &mut header)? {
+                break;
+            }
+            let size = be32(&header[4..]) as u64;
+            match &header[0..4] {
+                b"COMM" => {
+                    let body = read_body(reader, size)?;
+                    let (c, nb_channels, sample_rate) = parse_comm(&body, aifc)?;
+                    aiff.format = c.format;
+                    aiff.sample_rate = sample_rate;
+                    aiff.channels = vec![Vec::new(); nb_channels];
+                    comm = Some(c);
+                }
+                b"SSND" => {
+                    let c = match comm {
+                        Some(ref c) => c,
+                        None => return Err(invalid("the COMM chunk is missing before the SSND chunk")),
+                    };
+                    if size < 8 {
+                        return Err(invalid("the SSND chunk is too short"));
+                    }
+                    let mut offsets = [0u8; 8];
+                    reader.read_exact(&mut offsets)?;
+                    // The samples begin after `offset` bytes, which are usually none.
+                    let offset = (be32(&offsets) as u64).min(size - 8);
+                    skip(reader, offset)?;
+                    let block_align = aiff.channels.len() * c.format.bytes_per_sample();
+                    let data_size = (size - 8 - offset).min(c.nb_frames * block_align as u64);
+                    read_frames(reader, data_size, block_align, c.format, c.endianness, &mut aiff.channels)?;
+                    skip(reader, size - 8 - offset - data_size)?;
+                }
+                b"MARK" => aiff.marks = parse_mark(&read_body(reader, size)?),
+                _ => skip(reader, size)?,
+            }
+            // Chunks are padded to even sizes.
+            if size % 2 == 1 {
+                read_or_eof(reader, &mut [0u8; 1])?;
+            }
+        }
+
+        if comm.is_none() {
+            return Err(invalid("the COMM chunk is missing"));
+        }
+        Ok(aiff)
+    }
+
+    /// Reads an AIFF or AIFF-C file at `path`. See `read`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<Error>> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// The samples of the `channel`-th channel with the sample rate,
+    /// or `None` if there is no such channel.
+    pub fn sample_sequence(&self, channel: usize) -> Option<SampleSequence<'_, f32>> {
+        self.channels.get(channel).map(|data| SampleSequence {
+            data: &data[..],
+            sample_rate: self.sample_rate,
+        })
+    }
+}
+
+fn be16(b: &[u8]) -> u16 {
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+fn be32(b: &[u8]) -> u32 {
+    (be16(b) as u32) << 16 | be16(&b[2..]) as u32
+}
+
+/// Converts an 80-bit IEEE 754 extended precision number.
+fn extended(b: &[u8]) -> f64 {
+    let exponent = (be16(b) & 0x7fff) as i32;
+    let mantissa = (be32(&b[2..]) as u64) << 32 | be32(&b[6..]) as u64;
+    if exponent == 0 && mantissa == 0 {
+        return 0f64;
+    }
+    // The mantissa has an explicit integer bit at the top.
+    let v = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if b[0] & 0x80 != 0 {
+        -v
+    } else {
+        v
+    }
+}
+
+/// Returns the contents of the `COMM` chunk, the number of channels and the sample rate.
+fn parse_comm(body: &[u8], aifc: bool) -> Result<(Comm, usize, f64), Box<Error>> {
+    if body.len() < 18 || (aifc && body.len() < 22) {
+        return Err(invalid("the COMM chunk is too short"));
+    }
+    let nb_channels = be16(body) as usize;
+    let nb_frames = be32(&body[2..]) as u64;
+    let bits = be16(&body[6..]);
+    let sample_rate = extended(&body[8..18]);
+
+    // Integer samples are left-aligned in as many bytes as needed.
+    let integer = match bits {
+        1..=8 => Some(SampleFormat::I8),
+        9..=16 => Some(SampleFormat::I16),
+        17..=24 => Some(SampleFormat::I24),
+        25..=32 => Some(SampleFormat::I32),
+        _ => None,
+    };
+    let compression = if aifc { &body[18..22] } else { b"NONE" };
+    let (format, endianness) = match (compression, integer) {
+        (b"NONE", Some(f)) | (b"twos", Some(f)) => (f, Endianness::Big),
+        (b"sowt", Some(f)) => (f, Endianness::Little),
+        (b"fl32", _) | (b"FL32", _) => (SampleFormat::F32, Endianness::Big),
+        (b"fl64", _) | (b"FL64", _) => (SampleFormat::F64, Endianness::Big),
+        _ => return Err(invalid("unsupported sample format")),
+    };
+    if nb_channels == 0 || sample_rate.is_nan() || sample_rate <= 0f64 {
+        return Err(invalid("invalid COMM chunk"));
+    }
+    Ok((
+        Comm {
+            nb_frames,
+            format,
+            endianness,
+        },
+        nb_channels,
+        sample_rate,
+    ))
+}
+
+fn parse_mark(body: &[u8]) -> Vec<Mark> {
+    let mut marks = Vec::new();
+    if body.len() < 2 {
+        return marks;
+    }
+    let nb_marks = be16(body) as usize;
+    let mut rest = &body[2..];
+    while marks.len() < nb_marks && rest.len() >= 7 {
+        let position = be32(&rest[2..]) as usize;
+        let l = (rest[6] as usize).min(rest.len() - 7);
+        marks.push(Mark {
+            id: be16(rest),
+            name: String::from_utf8_lossy(&rest[7..7 + l]).into_owned(),
+            position: TimeRange::Samples(position, position),
+        });
+        // The name is a Pascal string padded to an even length including the count byte.
+        rest = &rest[rest.len().min(7 + l + (l + 1) % 2)..];
+    }
+    marks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        c.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    }
+
+    fn comm(nb_channels: u16, nb_frames: u32, bits: u16, compression: Option<&[u8]>) -> Vec<u8> {
+        let mut c = Vec::new();
+        c.extend_from_slice(&nb_channels.to_be_bytes());
+        c.extend_from_slice(&nb_frames.to_be_bytes());
+        c.extend_from_slice(&bits.to_be_bytes());
+        // 44100 as an 80-bit extended float.
+        c.extend_from_slice(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        if let Some(compression) = compression {
+            c.extend_from_slice(compression);
+            c.extend_from_slice(&[0, 0]);
+        }
+        c
+    }
+
+    fn form(id: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut f = b"FORM".to_vec();
+        f.extend_from_slice(&((body.len() + 4) as u32).to_be_bytes());
+        f.extend_from_slice(id);
+        f.extend_from_slice(&body);
+        f
+    }
+
+    fn ssnd(data: &[u8]) -> Vec<u8> {
+        chunk(b"SSND", &[&[0u8; 8][..], data].concat())
+    }
+
+    #[test]
+    fn aiff() {
+        // Stereo 16-bit with markers, and a frame beyond the number of frames.
+        let mut mark = 2u16.to_be_bytes().to_vec();
+        mark.extend_from_slice(&[0, 1, 0, 0, 0, 1, 5]);
+        mark.extend_from_slice(b"Verse");
+        mark.extend_from_slice(&[0, 2, 0, 0, 0, 2, 0, 0]);
+        let file = form(b"AIFF", &[
+            chunk(b"COMM", &comm(2, 2, 16, None)),
+            chunk(b"MARK", &mark),
+            ssnd(&[0x7f, 0xff, 0x80, 0, 0xe0, 0, 0, 0, 0x7f, 0xff, 0x7f, 0xff]),
+        ]);
+        let aiff = Aiff::read(&mut &file[..]).unwrap();
+        assert_eq!(aiff.sample_rate, 44100f64);
+        assert_eq!(aiff.format, SampleFormat::I16);
+        assert_eq!(aiff.channels, vec![vec![1f32, -0.25f32], vec![-1f32, 0f32]]);
+        assert_eq!(aiff.sample_sequence(0).unwrap().sample_rate, 44100f64);
+        assert_eq!(aiff.marks.len(), 2);
+        assert_eq!(aiff.marks[0].name, "Verse");
+        assert_eq!(aiff.marks[0].position, TimeRange::Samples(1, 1));
+        assert_eq!(aiff.marks[1].id, 2);
+        assert_eq!(aiff.marks[1].name, "");
+
+        // 12-bit samples are left-aligned in 2 bytes.
+        let file = form(b"AIFF", &[chunk(b"COMM", &comm(1, 1, 12, None)), ssnd(&[0xc0, 0])]);
+        assert_eq!(Aiff::read(&mut &file[..]).unwrap().channels, vec![vec![-0.5f32]]);
+
+        // Little-endian 24-bit and big-endian float in AIFF-C.
+        let file = form(b"AIFC", &[chunk(b"COMM", &comm(1, 1, 24, Some(b"sowt"))), ssnd(&[0, 0, 0xc0])]);
+        assert_eq!(Aiff::read(&mut &file[..]).unwrap().channels, vec![vec![-0.5f32]]);
+        let file = form(b"AIFC", &[chunk(b"COMM", &comm(1, 1, 32, Some(b"fl32"))), ssnd(&0.75f32.to_be_bytes())]);
+        let aiff = Aiff::read(&mut &file[..]).unwrap();
+        assert_eq!(aiff.format, SampleFormat::F32);
+        assert_eq!(aiff.channels, vec![vec![0.75f32]]);
+
+        assert!(Aiff::read(&mut &form(b"AIFC", &[chunk(b"COMM", &comm(1, 1, 16, Some(b"ima4")))])[..]).is_err());
+        assert!(Aiff::read(&mut &form(b"AIFF", &[ssnd(&[0, 0])])[..]).is_err());
+    }
+}
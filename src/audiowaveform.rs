@@ -0,0 +1,468 @@
+//! Export and import of peaks in the formats of BBC's audiowaveform,
+//! which are read by peaks.js and wavesurfer.js.
+//!
+//! Both the binary `.dat` format and the JSON format are supported,
+//! in versions 1 (a single channel) and 2 (multiple channels)
+//! with either 8-bit or 16-bit values.
+//!
+//! Amplitudes are normalized by the full scale of the `Sample` type
+//! (see `FullScale`), so that the full scale of 8-bit values is 128 and
+//! that of 16-bit values is 32768 as in audiowaveform.
+//! Imported peaks are normalized into `-1..1`.
+
+use std::error::Error;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+use error::{InvalidDataError, InvalidSizeError};
+use misc::*;
+use full_scale::FullScale;
+use binned::BinnedWaveformRenderer;
+
+/// The header of a peaks file, shared by the `.dat` and JSON formats.
+struct Header {
+    version: u32,
+    bits: u32,
+    sample_rate: u32,
+    samples_per_pixel: u32,
+    length: usize,
+    channels: usize,
+}
+
+/// Writes the min / max values of `channels` in the audiowaveform `.dat` format.
+///
+/// It will raise an error if
+///
+/// * `channels` is empty, or its elements differ in their bin sizes,
+///   sample rates or numbers of bins.
+/// * `version` is neither 1 nor 2, or it is 1 while there are multiple channels.
+/// * `bits` is neither 8 nor 16.
+/// * writing into `writer` fails.
+///
+/// # Arguments
+///
+/// * `channels` - The renderers of the channels. Their bin sizes are used
+///                as the numbers of samples per pixel.
+/// * `version` - The version of the format.
+/// * `bits` - The number of bits of each value.
+/// * `writer` - The destination of the peaks.
+pub fn write_dat<T: Sample + FullScale, W: Write>(channels: &[&BinnedWaveformRenderer<T>], version: u32, bits: u32, writer: &mut W) -> Result<(), Box<Error>> {
+    let header = check_channels(channels, version, bits)?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(&le32(header.version));
+    // Flags, where the least significant bit is set for 8-bit values.
+    bytes.extend_from_slice(&le32(if header.bits == 8 { 1 } else { 0 }));
+    bytes.extend_from_slice(&le32(header.sample_rate));
+    bytes.extend_from_slice(&le32(header.samples_per_pixel));
+    bytes.extend_from_slice(&le32(header.length as u32));
+    if header.version == 2 {
+        bytes.extend_from_slice(&le32(header.channels as u32));
+    }
+    for v in values(channels, header.length, header.bits) {
+        if header.bits == 8 {
+            bytes.push(v as i8 as u8);
+        } else {
+            bytes.extend_from_slice(&[v as u8, (v >> 8) as u8]);
+        }
+    }
+    writer.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Writes the min / max values of `channels` in the audiowaveform JSON format.
+///
+/// See `write_dat` for the arguments and errors.
+pub fn write_json<T: Sample + FullScale, W: Write>(channels: &[&BinnedWaveformRenderer<T>], version: u32, bits: u32, writer: &mut W) -> Result<(), Box<Error>> {
+    let header = check_channels(channels, version, bits)?;
+
+    let mut json = format!("{{\"version\":{},", header.version);
+    if header.version == 2 {
+        json.push_str(&format!("\"channels\":{},", header.channels));
+    }
+    json.push_str(&format!(
+        "\"sample_rate\":{},\"samples_per_pixel\":{},\"bits\":{},\"length\":{},\"data\":[",
+        header.sample_rate, header.samples_per_pixel, header.bits, header.length
+    ));
+    let values: Vec<String> = values(channels, header.length, header.bits).iter().map(|v| v.to_string()).collect();
+    json.push_str(&values.join(","));
+    json.push_str("]}");
+    writer.write_all(json.as_bytes())?;
+
+    Ok(())
+}
+
+/// Reads peaks in the audiowaveform `.dat` format, and creates a
+/// `BinnedWaveformRenderer` for each channel.
+///
+/// The amplitudes are normalized into `-1..1`, the numbers of samples per pixel
+/// are used as the bin sizes, and RMS values and `Gradient`s are not available
+/// as the samples are not. See `BinnedWaveformRenderer::get_minmax`
+/// for the min / max values.
+///
+/// It will raise an error if the data is not in a supported format,
+/// or reading from `reader` fails.
+///
+/// # Arguments
+///
+/// * `reader` - The source of the peaks.
+/// * `config` - See `WaveformConfig`. It is used by all of the channels.
+pub fn read_dat<R: Read>(reader: &mut R, config: WaveformConfig) -> Result<Vec<BinnedWaveformRenderer<f64>>, Box<Error>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let field = |i: usize| -> Result<u32, Box<Error>> {
+        match bytes.get(i * 4..i * 4 + 4) {
+            Some(b) => Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24),
+            None => Err(invalid("the header is too short")),
+        }
+    };
+    let version = field(0)?;
+    let channels = match version {
+        1 => 1,
+        2 => field(5)? as usize,
+        _ => return Err(invalid("unsupported version")),
+    };
+    let header = Header {
+        version,
+        bits: if field(1)? & 1 != 0 { 8 } else { 16 },
+        sample_rate: field(2)?,
+        samples_per_pixel: field(3)?,
+        length: field(4)? as usize,
+        channels,
+    };
+
+    let data = &bytes[if version == 1 { 20 } else { 24 }..];
+    let bytes_per_value = header.bits as usize / 8;
+    let nb_values = nb_values(&header)?;
+    match nb_values.checked_mul(bytes_per_value) {
+        Some(size) if size <= data.len() => {}
+        _ => return Err(invalid("the data is too short")),
+    }
+    let values: Vec<i32> = (0..nb_values)
+        .map(|i| if bytes_per_value == 1 {
+            data[i] as i8 as i32
+        } else {
+            (data[i * 2] as u16 | (data[i * 2 + 1] as u16) << 8) as i16 as i32
+        })
+        .collect();
+
+    renderers(&header, &values, config)
+}
+
+/// Reads peaks in the audiowaveform JSON format, and creates a
+/// `BinnedWaveformRenderer` for each channel.
+///
+/// See `read_dat`.
+pub fn read_json<R: Read>(reader: &mut R, config: WaveformConfig) -> Result<Vec<BinnedWaveformRenderer<f64>>, Box<Error>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut parser = JsonParser { bytes: text.as_bytes(), pos: 0 };
+    let (fields, data) = parser.object()?;
+    let field = |name: &str| -> Result<u32, Box<Error>> {
+        match fields.iter().find(|f| f.0 == name) {
+            Some(f) => u32::try_from(f.1).map_err(|_| invalid(&format!("\"{}\" is out of range", name))),
+            None => Err(invalid(&format!("\"{}\" is missing", name))),
+        }
+    };
+    let version = field("version")?;
+    let header = Header {
+        version,
+        bits: field("bits")?,
+        sample_rate: field("sample_rate")?,
+        samples_per_pixel: field("samples_per_pixel")?,
+        length: field("length")? as usize,
+        channels: match version {
+            1 => 1,
+            2 => field("channels")? as usize,
+            _ => return Err(invalid("unsupported version")),
+        },
+    };
+    if header.bits != 8 && header.bits != 16 {
+        return Err(invalid("unsupported bits"));
+    }
+    if data.len() < nb_values(&header)? {
+        return Err(invalid("the data is too short"));
+    }
+
+    renderers(&header, &data, config)
+}
+
+fn invalid(reason: &str) -> Box<Error> {
+    Box::new(InvalidDataError {
+        reason: reason.to_string(),
+    })
+}
+
+/// The number of the values in the data, which are a min / max pair
+/// per channel per pixel.
+fn nb_values(header: &Header) -> Result<usize, Box<Error>> {
+    header.length
+        .checked_mul(header.channels)
+        .and_then(|n| n.checked_mul(2))
+        .ok_or_else(|| invalid("the length is too large"))
+}
+
+fn le32(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+/// Checks if `channels` can be written in the format, and returns the header.
+fn check_channels<T: Sample>(channels: &[&BinnedWaveformRenderer<T>], version: u32, bits: u32) -> Result<Header, Box<Error>> {
+    let first = match channels.first() {
+        Some(first) => first,
+        None => return Err(Box::new(InvalidSizeError{var_name: "channels".to_string()})),
+    };
+    let length = first.get_minmax().data.len();
+    let consistent = channels.iter().all(|c| {
+        c.get_bin_size() == first.get_bin_size()
+            && c.get_sample_rate() == first.get_sample_rate()
+            && c.get_minmax().data.len() == length
+    });
+    if !consistent {
+        return Err(Box::new(InvalidSizeError{var_name: "channels".to_string()}));
+    }
+    if !(version == 2 || version == 1 && channels.len() == 1) {
+        return Err(Box::new(InvalidSizeError{var_name: "version".to_string()}));
+    }
+    if bits != 8 && bits != 16 {
+        return Err(Box::new(InvalidSizeError{var_name: "bits".to_string()}));
+    }
+
+    Ok(Header {
+        version,
+        bits,
+        sample_rate: first.get_sample_rate().round() as u32,
+        samples_per_pixel: first.get_bin_size() as u32,
+        length,
+        channels: channels.len(),
+    })
+}
+
+/// Converts the min / max values of `channels` into integers,
+/// interleaved as min and max of each channel for each bin.
+fn values<T: Sample + FullScale>(channels: &[&BinnedWaveformRenderer<T>], length: usize, bits: u32) -> Vec<i32> {
+    let full_scale = if bits == 8 { 128f64 } else { 32768f64 };
+    let to_int = |v: T| -> i32 {
+        (normalize(v) * full_scale).round().max(-full_scale).min(full_scale - 1f64) as i32
+    };
+    let mut values = Vec::with_capacity(length * channels.len() * 2);
+    for i in 0..length {
+        for c in channels.iter() {
+            let pair = c.get_minmax().data[i];
+            values.push(to_int(pair.min));
+            values.push(to_int(pair.max));
+        }
+    }
+    values
+}
+
+/// Normalizes `v` by the full scale of its type into `-1..1`.
+fn normalize<T: Sample + FullScale>(v: T) -> f64 {
+    let (lo, hi) = T::full_scale();
+    let v: f64 = v.into();
+    if lo < 0f64 {
+        v / lo.abs().max(hi)
+    } else {
+        // Unsigned integers are centered at the half of their range.
+        let center = (hi + 1f64) / 2f64;
+        (v - center) / center
+    }
+}
+
+/// Creates a renderer for each channel from the interleaved `values`.
+fn renderers(header: &Header, values: &[i32], config: WaveformConfig) -> Result<Vec<BinnedWaveformRenderer<f64>>, Box<Error>> {
+    if header.channels == 0 || header.length == 0 || header.samples_per_pixel == 0 {
+        return Err(invalid("no peaks"));
+    }
+    let full_scale = if header.bits == 8 { 128f64 } else { 32768f64 };
+    (0..header.channels)
+        .map(|c| {
+            let data = (0..header.length)
+                .map(|i| {
+                    let idx = (i * header.channels + c) * 2;
+                    MinMaxPair {
+                        min: values[idx] as f64 / full_scale,
+                        max: values[idx + 1] as f64 / full_scale,
+                    }
+                })
+                .collect();
+            BinnedWaveformRenderer::from_minmax(data, header.samples_per_pixel as usize, header.sample_rate as f64, config)
+        })
+        .collect()
+}
+
+/// The named integers in a JSON object.
+type Fields = Vec<(String, i64)>;
+
+/// A minimal parser of the JSON format, which only accepts an object
+/// of integers and an array of integers named `data`.
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `c` after whitespace.
+    fn expect(&mut self, c: u8) -> Result<(), Box<Error>> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) != Some(&c) {
+            return Err(invalid(&format!("'{}' is expected at {}", c as char, self.pos)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Consumes `c` after whitespace if it is next.
+    fn accept(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Box<Error>> {
+        self.expect(b'"')?;
+        let begin = self.pos;
+        while self.pos < self.bytes.len() && self.bytes[self.pos] != b'"' {
+            self.pos += 1;
+        }
+        let s = String::from_utf8_lossy(&self.bytes[begin..self.pos]).into_owned();
+        self.expect(b'"')?;
+        Ok(s)
+    }
+
+    fn integer(&mut self) -> Result<i64, Box<Error>> {
+        self.skip_whitespace();
+        let begin = self.pos;
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.bytes[begin..self.pos])
+            .parse()
+            .map_err(|_| invalid(&format!("an integer is expected at {}", begin)))
+    }
+
+    /// Parses the whole object, and returns its integer fields and `data`.
+    fn object(&mut self) -> Result<(Fields, Vec<i32>), Box<Error>> {
+        let mut fields = Vec::new();
+        let mut data = Vec::new();
+        self.expect(b'{')?;
+        if !self.accept(b'}') {
+            loop {
+                let name = self.string()?;
+                self.expect(b':')?;
+                if name == "data" {
+                    self.expect(b'[')?;
+                    if !self.accept(b']') {
+                        loop {
+                            let begin = self.pos;
+                            let v = i32::try_from(self.integer()?)
+                                .map_err(|_| invalid(&format!("the value at {} is out of range", begin)))?;
+                            data.push(v);
+                            if !self.accept(b',') {
+                                break;
+                            }
+                        }
+                        self.expect(b']')?;
+                    }
+                } else {
+                    fields.push((name, self.integer()?));
+                }
+                if !self.accept(b',') {
+                    break;
+                }
+            }
+            self.expect(b'}')?;
+        }
+        Ok((fields, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(data: &[i16], bin_size: usize) -> BinnedWaveformRenderer<i16> {
+        let config = WaveformConfig::new(-32768f64, 32767f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data,
+                sample_rate: 44100f64,
+            },
+            bin_size,
+            config,
+        ).unwrap()
+    }
+
+    #[test]
+    fn audiowaveform() {
+        let left = renderer(&[-32768, 16384, 0, 256, -256, 512], 2);
+        let right = renderer(&[0, 0, 100, 200, 32767, -1], 2);
+
+        let mut dat: Vec<u8> = Vec::new();
+        write_dat(&[&left], 1, 8, &mut dat).unwrap();
+        assert_eq!(
+            dat,
+            vec![
+                1, 0, 0, 0, 1, 0, 0, 0, 0x44, 0xac, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0,
+                0x80, 64, 0, 1, 0xff, 2,
+            ]
+        );
+
+        let mut json: Vec<u8> = Vec::new();
+        write_json(&[&left, &right], 2, 16, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json.clone()).unwrap(),
+            "{\"version\":2,\"channels\":2,\"sample_rate\":44100,\"samples_per_pixel\":2,\
+             \"bits\":16,\"length\":3,\"data\":[-32768,16384,0,0,0,256,100,200,-256,512,-1,32767]}"
+        );
+
+        // The values are read back in `-1..1`.
+        let channels = read_json(&mut &json[..], WaveformConfig::default()).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[1].get_bin_size(), 2);
+        assert_eq!(channels[1].get_minmax().data[2].max, 32767f64 / 32768f64);
+        assert_eq!(channels[0].get_minmax().data[0].min, -1f64);
+
+        let mut dat: Vec<u8> = Vec::new();
+        write_dat(&[&left, &right], 2, 16, &mut dat).unwrap();
+        let channels = read_dat(&mut &dat[..], WaveformConfig::default()).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].get_minmax().data[1].max, 256f64 / 32768f64);
+        assert_eq!(channels[0].get_sample_rate(), 44100f64);
+        // A bin at the full scale is regarded as clipped.
+        assert_eq!(channels[0].count_clipped(TimeRange::Samples(0, 6)), 1);
+
+        assert!(write_dat(&[&left, &right], 1, 8, &mut Vec::new()).is_err());
+        assert!(write_dat(&[&left], 2, 12, &mut Vec::new()).is_err());
+        assert!(read_dat(&mut &dat[..30], WaveformConfig::default()).is_err());
+        assert!(read_json(&mut &b"{\"version\":3}"[..], WaveformConfig::default()).is_err());
+
+        // Lengths and values that are out of range.
+        let mut huge = dat.clone();
+        huge[16..20].copy_from_slice(&[0xff; 4]);
+        huge[20..24].copy_from_slice(&[0xff; 4]);
+        assert!(read_dat(&mut &huge[..], WaveformConfig::default()).is_err());
+        let json = b"{\"version\":2,\"channels\":4294967295,\"sample_rate\":44100,\"samples_per_pixel\":2,\"bits\":16,\"length\":4294967295,\"data\":[]}";
+        assert!(read_json(&mut &json[..], WaveformConfig::default()).is_err());
+        let json = b"{\"version\":4294967298,\"channels\":1,\"sample_rate\":44100,\"samples_per_pixel\":2,\"bits\":16,\"length\":1,\"data\":[0,0]}";
+        assert!(read_json(&mut &json[..], WaveformConfig::default()).is_err());
+        let json = b"{\"version\":2,\"channels\":1,\"sample_rate\":44100,\"samples_per_pixel\":2,\"bits\":16,\"length\":1,\"data\":[0,4294967296]}";
+        assert!(read_json(&mut &json[..], WaveformConfig::default()).is_err());
+    }
+}
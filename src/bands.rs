@@ -0,0 +1,313 @@
+use std::error::Error;
+use std::f64;
+
+use super::misc::*;
+use super::error::*;
+use super::canvas::Canvas;
+use super::multi::MultiWaveformRenderer;
+
+/// A second-order IIR filter.
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    /// A Butterworth low-pass (`high_pass == false`) or
+    /// high-pass (`high_pass == true`) filter.
+    fn butterworth(frequency: f64, sample_rate: f64, high_pass: bool) -> Self {
+        let w0 = 2f64 * f64::consts::PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * f64::consts::FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        let a0 = 1f64 + alpha;
+        let (b0, b1) = if high_pass {
+            ((1f64 + cos) / 2f64, -(1f64 + cos))
+        } else {
+            ((1f64 - cos) / 2f64, 1f64 - cos)
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b0 / a0,
+            a1: -2f64 * cos / a0,
+            a2: (1f64 - alpha) / a0,
+        }
+    }
+
+    /// Filters `data` in place.
+    fn process(&self, data: &mut [f64]) {
+        let (mut x1, mut x2, mut y1, mut y2) = (0f64, 0f64, 0f64, 0f64);
+        for v in data.iter_mut() {
+            let x0 = *v;
+            let y0 = self.b0 * x0 + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+            x2 = x1;
+            x1 = x0;
+            y2 = y1;
+            y1 = y0;
+            *v = y0;
+        }
+    }
+}
+
+/// A renderer that splits the samples into low, mid and high frequency
+/// bands and draws their envelopes on top of each other, each in its own `Color`.
+///
+/// Each band is held by a `MultiWaveformRenderer`, so the bin sizes
+/// are chosen automatically as well.
+pub struct BandedWaveformRenderer {
+    pub low: MultiWaveformRenderer<f64>,
+    pub mid: MultiWaveformRenderer<f64>,
+    pub high: MultiWaveformRenderer<f64>,
+}
+
+impl BandedWaveformRenderer {
+    /// The constructor.
+    ///
+    /// Every band is cut by 4th order Linkwitz-Riley filters (two cascaded
+    /// Butterworth filters): the low band by a low-pass at `crossovers.0`,
+    /// the high band by a high-pass at `crossovers.1`, and the mid band by
+    /// a high-pass at `crossovers.0` followed by a low-pass at `crossovers.1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples to be split into bands.
+    /// * `crossovers` - The `(low / mid, mid / high)` crossover frequencies in Hz.
+    ///                  They must be in ascending order and below the Nyquist frequency.
+    /// * `bin_sizes` - The sizes of the bins which the min / max values will be binned
+    ///                into.
+    /// * `config` - See `WaveformConfig`. The foreground `Color` is not used.
+    /// * `colors` - The `Color`s of the low, mid and high bands, in this order.
+    ///              Their formats must be consistent with the background.
+    pub fn new<T: Sample>(
+        samples: &SampleSequence<T>,
+        crossovers: (f64, f64),
+        bin_sizes: &[usize],
+        config: WaveformConfig,
+        colors: [Color; 3],
+    ) -> Result<Self, Box<Error>> {
+        let (low_mid, mid_high) = crossovers;
+        if !(0f64 < low_mid && low_mid < mid_high && mid_high < samples.sample_rate / 2f64) {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "crossovers".to_string(),
+            }));
+        }
+
+        let mut configs = [config; 3];
+        for (c, color) in configs.iter_mut().zip(colors.iter()) {
+            c.set_colors(config.get_background(), *color)?;
+        }
+
+        let data: Vec<f64> = samples.data.iter().map(|s| (*s).into()).collect();
+        let mut low = data.clone();
+        let mut mid = data.clone();
+        let mut high = data;
+        let low_lp = Biquad::butterworth(low_mid, samples.sample_rate, false);
+        let low_hp = Biquad::butterworth(low_mid, samples.sample_rate, true);
+        let high_lp = Biquad::butterworth(mid_high, samples.sample_rate, false);
+        let high_hp = Biquad::butterworth(mid_high, samples.sample_rate, true);
+        for _ in 0..2 {
+            low_lp.process(&mut low[..]);
+            low_hp.process(&mut mid[..]);
+            high_hp.process(&mut high[..]);
+        }
+        for _ in 0..2 {
+            high_lp.process(&mut mid[..]);
+        }
+
+        let band = |data: &[f64], config: WaveformConfig| {
+            MultiWaveformRenderer::new(
+                &SampleSequence {
+                    data,
+                    sample_rate: samples.sample_rate,
+                },
+                bin_sizes,
+                config,
+            )
+        };
+
+        Ok(Self {
+            low: band(&low[..], configs[0])?,
+            mid: band(&mid[..], configs[1])?,
+            high: band(&high[..], configs[2])?,
+        })
+    }
+
+    /// Renders an image as a `Vec<u8>`.
+    ///
+    /// `None` will be returned if the area of the specified `shape` is equal to zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    pub fn render_vec(&self, range: TimeRange, shape: (usize, usize)) -> Option<Vec<u8>> {
+        let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return None;
+        }
+
+        let bytes_per_pixel = match self.low.get_optimal_binned(range, shape) {
+            Some(b) => b.config.get_background().bytes_per_pixel(),
+            None => return None,
+        };
+        let mut img = vec![0u8; w * h * bytes_per_pixel];
+
+        self.render_write(range, (0, 0), shape, &mut img[..], shape).unwrap();
+
+        Some(img)
+    }
+
+    /// Writes the image into a mutable reference to a slice.
+    ///
+    /// The background, anti-aliasing, orientation and amplitude range
+    /// follow the `WaveformConfig` of the low band.
+    /// The envelopes are drawn from the low band to the high band,
+    /// so the higher bands are drawn on top of the lower ones.
+    ///
+    /// It will raise an error if
+    ///
+    /// * the area of the specified `shape` is equal to zero.
+    /// * either the width or height of the `shape` exceeds that of the `full_shape`
+    ///   of `img`.
+    /// * the length of `img` is not long enough to contain the result.
+    ///   `(offsets.0 + shape.0) * (offsets.1 + shape.1) * (Bytes per pixel) <= img.len()`
+    ///   must be satisfied.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `offsets` - The `(x-offset, y-offset)` of the part of the `img` that is
+    ///               going to be overwritten in in pixels.
+    ///               Specifies the starting position to write into `img`.
+    /// * `shape` - The `(width, height)` of the part of the `img` that is going
+    ///             to be overwritten in pixels.
+    /// * `img`   - A mutable reference to the slice to write the result into.
+    /// * `full_shape` - The `(width, height)` of the whole `img` in pixels.
+    ///
+    pub fn render_write(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        let binned = match (
+            self.low.get_optimal_binned(range, shape),
+            self.mid.get_optimal_binned(range, shape),
+            self.high.get_optimal_binned(range, shape),
+        ) {
+            (Some(l), Some(m), Some(h)) => [l, m, h],
+            _ => return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        };
+        let config = &binned[0].config;
+
+        let mut canvas = Canvas::new(
+            img,
+            offsets,
+            shape,
+            full_shape,
+            config,
+        )?;
+        let (w, h) = config.orientation.logical_shape(shape);
+
+        let bg_colors = config.get_background().to_bytes();
+        let fg_colors: Vec<[u8; 4]> = binned.iter().map(|b| b.config.get_foreground().to_bytes()).collect();
+
+        let mut low = binned[0].columns(range, w);
+        let mut mid = binned[1].columns(range, w);
+        let mut high = binned[2].columns(range, w);
+        for x in 0..w {
+            canvas.fill_background(x, 0, h, &bg_colors);
+            for (column, colors) in [low.next(), mid.next(), high.next()].iter().zip(fg_colors.iter()) {
+                if let Some(ref column) = *column {
+                    let (top, bottom) = config.envelope(column.min, column.max, h);
+                    canvas.fill_heights(x, top, bottom, colors, config.anti_aliased);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64;
+
+    use super::BandedWaveformRenderer;
+    use misc::*;
+    use multi::MultiWaveformRenderer;
+
+    #[test]
+    fn bands() {
+        // 50 Hz for the first half, and 5000 Hz for the second half.
+        let sample_rate = 44100f64;
+        let mut samples: Vec<f64> = Vec::new();
+        for t in 0..44100 {
+            let f = if t < 22050 { 50f64 } else { 5000f64 };
+            samples.push(((t as f64) / sample_rate * f * 2f64 * f64::consts::PI).sin());
+        }
+        let config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(0), Color::Scalar(0)).unwrap();
+        let bwr = BandedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate,
+            },
+            (200f64, 2000f64),
+            &[10, 100],
+            config,
+            [Color::Scalar(1), Color::Scalar(2), Color::Scalar(3)],
+        ).unwrap();
+
+        // Look at the top rows, away from the transition.
+        let (w, h) = (100, 20);
+        let v = bwr.render_vec(TimeRange::Seconds(0f64, 1f64), (w, h)).unwrap();
+        assert_eq!(v[w + 10], 1);
+        assert_eq!(v[w + 90], 3);
+
+        assert!(BandedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate,
+            },
+            (2000f64, 200f64),
+            &[10],
+            config,
+            [Color::Scalar(1), Color::Scalar(2), Color::Scalar(3)],
+        ).is_err());
+    }
+
+    #[test]
+    fn band_energy() {
+        // Tones at the centers of the low, mid and high bands.
+        let sample_rate = 44100f64;
+        let crossovers = (200f64, 2000f64);
+        let frequencies = [50f64, (crossovers.0 * crossovers.1).sqrt(), 8000f64];
+        for (i, f) in frequencies.iter().enumerate() {
+            let samples: Vec<f64> = (0..44100)
+                .map(|t| ((t as f64) / sample_rate * f * 2f64 * f64::consts::PI).sin())
+                .collect();
+            let config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(0), Color::Scalar(0)).unwrap();
+            let bwr = BandedWaveformRenderer::new(
+                &SampleSequence {
+                    data: &samples[..],
+                    sample_rate,
+                },
+                crossovers,
+                &[100],
+                config,
+                [Color::Scalar(1), Color::Scalar(2), Color::Scalar(3)],
+            ).unwrap();
+
+            // The energy of the second half, after the filters have settled.
+            let energy = |band: &MultiWaveformRenderer<f64>| -> f64 {
+                let binned = &band.binned[&100];
+                let squares = &binned.square_sums;
+                squares[squares.len() / 2..].iter().sum()
+            };
+            let energies = [energy(&bwr.low), energy(&bwr.mid), energy(&bwr.high)];
+            for (j, e) in energies.iter().enumerate() {
+                if i != j {
+                    assert!(energies[i] > e * 100f64, "{} Hz: {:?}", f, energies);
+                }
+            }
+        }
+    }
+}
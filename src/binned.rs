@@ -1,24 +1,40 @@
 use std::error::Error;
 use std::cmp;
+use std::io::Write;
 use error::InvalidSizeError;
 use misc::*;
-
-#[cfg(not(feature = "rlibc"))]
-use std::io::Write;
-
-#[cfg(feature = "rlibc")]
-use rlibc;
+use full_scale::FullScale;
+use canvas::Canvas;
+use svg::write_svg;
+#[cfg(feature = "png")]
+use png::write_png;
+use netpbm::write_pnm;
+use bmp::write_bmp;
+use cache::{read_levels, write_levels, CacheSample};
+use std::io::Read;
 
 
 /// A fast "binned" waveform renderer.
 ///
 /// Minimum / maximum amplitude values are binned to reduce
 /// calculation and memory usage.
+/// The sums of squared amplitudes, the numbers of zero crossings
+/// and the numbers of clipped samples are binned as well so that RMS values
+/// can be rendered, the waveform can be colored by a `Gradient`
+/// and clipped columns can be highlighted.
+///
+/// Optionally, it can keep the samples to draw
+/// interpolated lines or stems when zoomed in below one sample per pixel.
 pub struct BinnedWaveformRenderer<T: Sample> {
     pub config: WaveformConfig,
-    sample_rate: f64,
-    bin_size: usize,
-    minmax: MinMaxPairSequence<T>,
+    pub(crate) sample_rate: f64,
+    pub(crate) bin_size: usize,
+    pub(crate) nb_samples: usize,
+    pub(crate) minmax: MinMaxPairSequence<T>,
+    pub(crate) square_sums: Vec<f64>,
+    pub(crate) zero_crossings: Vec<u32>,
+    pub(crate) clipped: Vec<u32>,
+    pub(crate) samples: Option<Vec<T>>,
 }
 
 impl<T: Sample> BinnedWaveformRenderer<T> {
@@ -26,53 +42,192 @@ impl<T: Sample> BinnedWaveformRenderer<T> {
     ///
     /// # Arguments
     ///
-    /// * `samples` - The samples that will be used to calculate binned min / max values.
+    /// * `samples` - The samples that will be used to calculate binned min / max values,
+    ///               sums of squares, numbers of zero crossings and
+    ///               numbers of clipped samples.
     ///               It must also contain the sample rate that is used by
     ///               `BinnedWaveformRenderer` to render images when given a
     ///               `TimeRange::Seconds`.
     /// * `bin_size` - The size of the bins which the min / max values will be binned
     ///                into.
-    /// * `config` - See `WaveformConfig`.
-    pub fn new(samples: &SampleSequence<T>, bin_size: usize, config: WaveformConfig) -> Result<BinnedWaveformRenderer<T>, Box<Error>> {
-        let mut data: Vec<MinMaxPair<T>> = Vec::new();
-        let nb_samples = samples.data.len();
-
-        if bin_size > nb_samples {
+    /// * `config` - See `WaveformConfig`. Its `clipping_threshold` is used here.
+    pub fn new(samples: &SampleSequence<T>, bin_size: usize, config: WaveformConfig) -> Result<BinnedWaveformRenderer<T>, Box<Error>>
+    where
+        T: FullScale,
+    {
+        if bin_size == 0 || bin_size > samples.data.len() {
             return Err(Box::new(InvalidSizeError {
                 var_name: "bin_size".to_string(),
             }));
         }
 
-        let nb_bins = (nb_samples as f64 / bin_size as f64).ceil() as usize;
+        let mut binner = Binner::new(bin_size, &config);
+        for s in samples.data.iter() {
+            binner.push(*s);
+        }
+        binner.finish(samples.sample_rate, config)
+    }
 
-        for x in 0..nb_bins {
-            let mut min = samples.data[x * bin_size + 0];
-            let mut max = samples.data[x * bin_size + 0];
-            if bin_size > 1 {
-                for i in 1..bin_size {
-                    let idx = x * bin_size + i;
-                    if idx >= nb_samples {
-                        break;
-                    }
-                    let s = samples.data[idx];
-                    if s > max {
-                        max = s;
-                    } else if s < min {
-                        min = s;
-                    }
+    /// Creates a `BinnedWaveformRenderer` from precomputed min / max values,
+    /// such as those read from a peaks file.
+    ///
+    /// As the samples are not available, the sums of squares and the numbers of
+    /// zero crossings are regarded as zero, and a bin is regarded as clipped
+    /// if either of its values reaches the limits of `config.clipping_threshold`.
+    pub(crate) fn from_minmax(data: Vec<MinMaxPair<T>>, bin_size: usize, sample_rate: f64, config: WaveformConfig) -> Result<BinnedWaveformRenderer<T>, Box<Error>>
+    where
+        T: FullScale,
+    {
+        if bin_size == 0 || data.is_empty() {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "bin_size and/or data".to_string(),
+            }));
+        }
+
+        let (clip_min, clip_max) = config.clipping_limits::<T>();
+        let clipped = data.iter()
+            .map(|p| (p.min.into() <= clip_min || p.max.into() >= clip_max) as u32)
+            .collect();
+        let nb_bins = data.len();
+        Ok(Self {
+            config,
+            bin_size,
+            nb_samples: nb_bins * bin_size,
+            minmax: MinMaxPairSequence { data },
+            square_sums: vec![0f64; nb_bins],
+            zero_crossings: vec![0; nb_bins],
+            clipped,
+            samples: None,
+            sample_rate,
+        })
+    }
+
+    /// Keeps the samples so that lines interpolated between them
+    /// or stems can be drawn when `WaveformConfig::interpolation` or
+    /// the `Stems` of the `WaveformConfig` are set.
+    ///
+    /// The samples are moved into the renderer and kept alongside the bins,
+    /// so the memory they take is not freed until `discard_samples` is called.
+    /// As the renderer does not borrow the samples given to the constructor,
+    /// this requires an owned copy of them. To avoid it, use
+    /// `render_write_with_samples` instead.
+    ///
+    /// It will raise an error if the number of the samples differs from
+    /// that given to the constructor. Their contents are not checked.
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The same samples that were given to the constructor.
+    pub fn keep_samples(&mut self, samples: Vec<T>) -> Result<(), Box<Error>> {
+        if samples.len() != self.nb_samples {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "samples".to_string(),
+            }));
+        }
+
+        self.samples = Some(samples);
+
+        Ok(())
+    }
+
+    /// Discards the samples kept by `keep_samples`.
+    pub fn discard_samples(&mut self) {
+        self.samples = None;
+    }
+
+    /// Returns `true` if the samples are kept.
+    pub fn has_samples(&self) -> bool {
+        self.samples.is_some()
+    }
+
+    /// Creates a `BinnedWaveformRenderer` with a larger bin size
+    /// by merging the bins of this one, without looking at the samples again.
+    ///
+    /// Samples kept by `keep_samples` are not copied into the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `bin_size` - The new bin size. It must be a multiple of the current
+    ///                bin size.
+    pub fn rebin(&self, bin_size: usize) -> Result<BinnedWaveformRenderer<T>, Box<Error>> {
+        if bin_size == 0 || bin_size % self.bin_size != 0 || bin_size > self.nb_samples {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "bin_size".to_string(),
+            }));
+        }
+
+        let factor = bin_size / self.bin_size;
+        let mut data: Vec<MinMaxPair<T>> = Vec::new();
+        let mut square_sums: Vec<f64> = Vec::new();
+        let mut zero_crossings: Vec<u32> = Vec::new();
+        let mut clipped: Vec<u32> = Vec::new();
+        for begin in (0..self.minmax.data.len()).step_by(factor) {
+            let end = cmp::min(begin + factor, self.minmax.data.len());
+            let mut pair = self.minmax.data[begin];
+            for p in self.minmax.data[begin + 1..end].iter() {
+                if p.min < pair.min {
+                    pair.min = p.min;
+                }
+                if p.max > pair.max {
+                    pair.max = p.max;
                 }
             }
-            data.push(MinMaxPair { min: min, max: max });
+            data.push(pair);
+            square_sums.push(self.square_sums[begin..end].iter().sum());
+            zero_crossings.push(self.zero_crossings[begin..end].iter().sum());
+            clipped.push(self.clipped[begin..end].iter().sum());
         }
-        let minmax = MinMaxPairSequence { data: data };
+
         Ok(Self {
-            config: config,
+            config: self.config,
             bin_size: bin_size,
-            minmax: minmax,
-            sample_rate: samples.sample_rate,
+            nb_samples: self.nb_samples,
+            minmax: MinMaxPairSequence { data: data },
+            square_sums: square_sums,
+            zero_crossings: zero_crossings,
+            clipped: clipped,
+            samples: None,
+            sample_rate: self.sample_rate,
         })
     }
 
+    /// Estimates the frequency in Hz of the samples in the bins
+    /// from `bin_begin` (inclusive) to `bin_end` (exclusive)
+    /// by their zero-crossing rate.
+    fn zero_crossing_frequency(&self, bin_begin: usize, bin_end: usize) -> f64 {
+        let zero_crossings: u32 = self.zero_crossings[bin_begin..bin_end].iter().sum();
+        let nb_samples = cmp::min(bin_end * self.bin_size, self.nb_samples) - bin_begin * self.bin_size;
+        if nb_samples == 0 {
+            return 0f64;
+        }
+        // A sine wave crosses zero twice per period.
+        zero_crossings as f64 / nb_samples as f64 * self.sample_rate / 2f64
+    }
+
+    /// Counts the clipped samples in the bins that the samples within `range` fall in.
+    ///
+    /// See `WaveformConfig::clipping_threshold`.
+    pub fn count_clipped(&self, range: TimeRange) -> u32 {
+        let (begin, end) = range.to_sample_tuple(self.sample_rate);
+        let bin_begin = cmp::min(begin / self.bin_size, self.clipped.len());
+        let bin_end = cmp::min(end.div_ceil(self.bin_size), self.clipped.len());
+        if bin_end <= bin_begin {
+            return 0;
+        }
+        self.clipped[bin_begin..bin_end].iter().sum()
+    }
+
+    /// Calculates the RMS value of the samples in the bins
+    /// from `bin_begin` (inclusive) to `bin_end` (exclusive).
+    fn rms(&self, bin_begin: usize, bin_end: usize) -> f64 {
+        let square_sum: f64 = self.square_sums[bin_begin..bin_end].iter().sum();
+        let nb_samples = cmp::min(bin_end * self.bin_size, self.nb_samples) - bin_begin * self.bin_size;
+        if nb_samples == 0 {
+            return 0f64;
+        }
+        (square_sum / nb_samples as f64).sqrt()
+    }
+
 
     /// Renders an image as a `Vec<u8>`.
     ///
@@ -89,11 +244,7 @@ impl<T: Sample> BinnedWaveformRenderer<T> {
         }
 
 
-        let mut img = match self.config.get_background() {
-            Color::Scalar(_) => vec![0u8; w * h],
-            Color::Vector3{..} => vec![0u8; w * h * 3],
-            Color::Vector4{..} => vec![0u8; w * h * 4],
-        };
+        let mut img = vec![0u8; w * h * self.config.get_background().bytes_per_pixel()];
         
         self.render_write(range, (0, 0), shape, &mut img[..], shape).unwrap();
 
@@ -119,238 +270,461 @@ impl<T: Sample> BinnedWaveformRenderer<T> {
     ///               Specifies the starting position to write into `img`.
     /// * `shape` - The `(width, height)` of the part of the `img` that is going 
     ///             to be overwritten in pixels.
+    ///             In the `Vertical` orientation, time runs along the height.
     /// * `img`   - A mutable reference to the slice to write the result into.
     /// * `full_shape` - The `(width, height)` of the whole `img` in pixels.
     ///
     pub fn render_write(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        let canvas = Canvas::new(img, offsets, shape, full_shape, &self.config)?;
+        self.render(range, canvas, None, None)
+    }
+
+    /// Writes the image into a mutable reference to a slice,
+    /// drawing interpolated lines or stems from borrowed samples
+    /// instead of those kept by `keep_samples`.
+    ///
+    /// In addition to those of `render_write`, it will raise an error if
+    /// the number of `samples` differs from that given to the constructor.
+    /// Their contents are not checked.
+    ///
+    /// # Arguments
+    ///
+    /// See `render_write`, with the following addition:
+    ///
+    /// * `samples` - The same samples that were given to the constructor.
+    pub fn render_write_with_samples(&self, samples: &[T], range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        if samples.len() != self.nb_samples {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "samples".to_string(),
+            }));
+        }
+        let canvas = Canvas::new(img, offsets, shape, full_shape, &self.config)?;
+        self.render(range, canvas, None, Some(samples))
+    }
+
+    /// Renders an image with an `Overlay` as a `Vec<u8>`.
+    ///
+    /// `None` will be returned if the area of the specified `shape` is equal to zero
+    /// or the formats of the `Color`s of `overlay` are inconsistent with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    /// * `overlay` - See `Overlay`.
+    pub fn render_vec_with_overlay(&self, range: TimeRange, shape: (usize, usize), overlay: &Overlay) -> Option<Vec<u8>> {
         let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return None;
+        }
+
+        let mut img = vec![0u8; w * h * self.config.get_background().bytes_per_pixel()];
+
+        self.render_write_with_overlay(range, (0, 0), shape, &mut img[..], shape, overlay).ok()?;
+
+        Some(img)
+    }
+
+    /// Writes the image with an `Overlay` into a mutable reference to a slice.
+    ///
+    /// In addition to those of `render_write`, it will raise an error if
+    /// the formats of the `Color`s of `overlay` are inconsistent with `config`.
+    ///
+    /// # Arguments
+    ///
+    /// See `render_write`, with the following addition:
+    ///
+    /// * `overlay` - See `Overlay`.
+    pub fn render_write_with_overlay(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize), overlay: &Overlay) -> Result<(), Box<Error>> {
+        overlay.check_format(&self.config)?;
+        let canvas = Canvas::new(img, offsets, shape, full_shape, &self.config)?;
+        self.render(range, canvas, Some(overlay), None)
+    }
+
+    /// Writes the envelope of the samples within `range` into `writer`
+    /// as an SVG document.
+    ///
+    /// The background and the envelope are drawn in the `Color`s of `config`,
+    /// and the envelope follows its `Orientation`, `DisplayMode` and `AmplitudeScale`.
+    /// Other features such as the RMS band, `Gradient`s and `Bars` are
+    /// only drawn into raster images.
+    ///
+    /// It will raise an error if the area of the specified `shape` is equal to zero
+    /// or writing into `writer` fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the document in user units.
+    ///             Each column of the envelope is one unit long along the time axis.
+    /// * `writer` - The destination of the document.
+    pub fn render_svg<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        let (w, h) = self.config.orientation.logical_shape(shape);
         if w == 0 || h == 0 {
             return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
         }
+        write_svg(&self.config, self.columns(range, w), shape, writer)
+    }
+
+    /// Renders an image and writes it into `writer` as a PNG image,
+    /// which is grayscale, RGB or RGBA depending on the `Color` format.
+    ///
+    /// It will raise an error if the area of the specified `shape` is equal to zero
+    /// or writing into `writer` fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    /// * `writer` - The destination of the PNG image.
+    #[cfg(feature = "png")]
+    pub fn render_png<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        let img = match self.render_vec(range, shape) {
+            Some(img) => img,
+            None => return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()})),
+        };
+        write_png(&img[..], shape, self.config.get_background().bytes_per_pixel(), writer)
+    }
+
+    /// Renders an image and writes it into `writer` in the Netpbm format,
+    /// which is PGM, PPM or PAM depending on the `Color` format.
+    ///
+    /// It will raise an error if the area of the specified `shape` is equal to zero
+    /// or writing into `writer` fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    /// * `writer` - The destination of the image.
+    pub fn render_pnm<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        let img = match self.render_vec(range, shape) {
+            Some(img) => img,
+            None => return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()})),
+        };
+        write_pnm(&img[..], shape, self.config.get_background().bytes_per_pixel(), writer)
+    }
+
+    /// Renders an image and writes it into `writer` as a BMP image.
+    ///
+    /// See `render_pnm`.
+    pub fn render_bmp<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        let img = match self.render_vec(range, shape) {
+            Some(img) => img,
+            None => return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()})),
+        };
+        write_bmp(&img[..], shape, self.config.get_background().bytes_per_pixel(), writer)
+    }
+
+    /// Renders into `canvas` with `samples` if given, or with the kept samples otherwise.
+    fn render(&self, range: TimeRange, mut canvas: Canvas, overlay: Option<&Overlay>, samples: Option<&[T]>) -> Result<(), Box<Error>> {
+        let (begin, end) = range.to_sample_tuple(self.sample_rate);
+
+        // From here on, `w` and `h` are the lengths along
+        // the time axis and the amplitude axis.
+        let (w, h) = canvas.shape();
+
+        let samples_per_pixel = ((end - begin) as f64) / (w as f64);
+
+        // Colors are converted into bytes once, outside the loop.
+        let bg_colors = self.config.get_background().to_bytes();
+        let fg_colors = self.config.get_foreground().to_bytes();
+        let rms_colors = self.config.get_rms().unwrap_or(self.config.get_foreground()).to_bytes();
+
+        if samples_per_pixel < 1f64 && self.config.bars.is_none() {
+            let stems = match self.config.get_stems() {
+                Some(stems) => 1f64 / samples_per_pixel >= stems.threshold,
+                None => false,
+            };
+            if let Some(samples) = samples.or_else(|| self.samples.as_ref().map(|s| &s[..])) {
+                if stems || self.config.interpolation.is_some() {
+                    self.render_samples(&mut canvas, samples, begin, samples_per_pixel, stems, overlay);
+                    return Ok(());
+                }
+            }
+        }
 
-        let (fullw, fullh) = full_shape;
-        if fullw < w || fullh < h {
-            return Err(Box::new(InvalidSizeError{var_name: "shape and/or full_shape".to_string()}));
+        // Plain envelopes are drawn by the fast path.
+        if overlay.is_none() && self.is_plain() {
+            self.render_plain(&mut canvas, range, w, &bg_colors, &fg_colors);
+            return Ok(());
         }
 
-        let (offx, offy) = offsets;
+        let columns: Box<Iterator<Item = Column<T>>> = match self.config.bars {
+            Some(bars) => Box::new(BarColumns::new(self.columns(range, w), bars)),
+            None => Box::new(self.columns(range, w)),
+        };
 
-        // Check if we have enough bytes in `img`
-        match self.config.get_background() {
-            Color::Scalar(_) => {
-                if (offx + w) * (offy + h) > img.len() {
-                    return Err(Box::new(InvalidSizeError{var_name: "offsets and/or shape".to_string()}));
+        for (x, column) in columns.enumerate() {
+            let (mut min, mut max): (f64, f64) = (column.min.into(), column.max.into());
+            let mut rms: Option<f64> = None;
+            let mut colors = [bg_colors, fg_colors, rms_colors];
+            if let Some((range_start, range_end)) = column.bins {
+                if self.config.get_rms().is_some() {
+                    rms = Some(self.rms(range_start, range_end));
                 }
-            },
-            Color::Vector3{..} => {
-                if (offx + w) * (offy + h) * 3 > img.len() {
-                    return Err(Box::new(InvalidSizeError{var_name: "offsets and/or shape".to_string()}));
+                if let Some(gradient) = self.config.get_gradient() {
+                    colors[1] = gradient.color_at(self.zero_crossing_frequency(range_start, range_end)).to_bytes();
                 }
-            },
-            Color::Vector4{..} => {
-                if (offx + w) * (offy + h) * 4 > img.len() {
-                    return Err(Box::new(InvalidSizeError{var_name: "offsets and/or shape".to_string()}));
+                if let Some(clipping) = self.config.get_clipping() {
+                    if self.clipped[range_start..range_end].iter().any(|c| *c > 0) {
+                        colors[1] = clipping.to_bytes();
+                        colors[2] = colors[1];
+                    }
                 }
-            },
-        }
+                if let Some(Bars { aggregation: Aggregation::Rms, .. }) = self.config.bars {
+                    let rms = self.rms(range_start, range_end);
+                    min = -rms;
+                    max = rms;
+                }
+            }
+            if let Some(overlay) = overlay {
+                if !self.apply_overlay(overlay, &mut canvas, x, begin, samples_per_pixel, &mut colors) {
+                    continue;
+                }
+            }
+            let [bg_colors, fg_colors, rms_colors] = colors;
 
-        let (begin, end) = match range {
-            TimeRange::Seconds(b, e) => (
-                (b * self.sample_rate) as usize,
-                (e * self.sample_rate) as usize,
-            ),
-            TimeRange::Samples(b, e) => (b, e),
-        };
-        let nb_samples = end - begin;
-        let samples_per_pixel = (nb_samples as f64) / (w as f64);
-        let bins_per_pixel = samples_per_pixel / (self.bin_size as f64);
-        let bins_per_pixel_floor = bins_per_pixel.floor() as usize;
-        let bins_per_pixel_ceil = bins_per_pixel.ceil() as usize;
-
-        let offset_bin_idx = begin / self.bin_size;
-        let mut start_bin_idx = offset_bin_idx;
-        for x in 0..w {
-            let inc = if ((start_bin_idx - offset_bin_idx) as f64 + 1f64) / (x as f64) < bins_per_pixel {
-                bins_per_pixel_ceil
-            } else {
-                bins_per_pixel_floor
+            let (mut top, mut bottom) = self.config.envelope(min, max, h);
+            if let Some(bars) = self.config.bars {
+                let (t, b) = self.round_corners(&bars, x, top, bottom);
+                top = t;
+                bottom = b;
+            }
+            let rms_band = rms.map(|rms| self.config.envelope(-rms, rms, h));
+
+            if self.config.anti_aliased {
+                // Unlike below, the edges are kept in fractional pixels
+                // so that partially covered pixels can be blended.
+                let translate = |u: f64| -> f64 { (h as f64 - u).max(0f64).min(h as f64) };
+                let min_translated = translate(bottom);
+                let max_translated = translate(top);
+
+                canvas.fill_background(x, 0, h, &bg_colors);
+                canvas.fill_fractional(x, max_translated, min_translated, &fg_colors);
+                if let Some((rms_top, rms_bottom)) = rms_band {
+                    canvas.fill_fractional(
+                        x,
+                        translate(rms_top).max(max_translated),
+                        translate(rms_bottom).min(min_translated),
+                        &rms_colors,
+                    );
+                }
+                continue;
+            }
+
+            let translate = |u: f64| -> usize { h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize };
+            let min_translated = translate(bottom);
+            let max_translated = translate(top);
+
+            // The RMS band is drawn inside the min / max envelope.
+            let (rms_top, rms_bottom) = match rms_band {
+                Some((rms_top, rms_bottom)) => (
+                    cmp::min(min_translated, cmp::max(max_translated, translate(rms_top))),
+                    cmp::min(min_translated, cmp::max(max_translated, translate(rms_bottom))),
+                ),
+                None => (min_translated, min_translated),
             };
 
-            let mut min: T;
-            let mut max: T;
-            if start_bin_idx < self.minmax.data.len() - 1 {
-                let ref d = self.minmax.data[start_bin_idx];
-                min = d.min;
-                max = d.max;
-                let range_start = start_bin_idx;
-                let range_end = if start_bin_idx + inc <= self.minmax.data.len() {
-                    start_bin_idx + inc
-                } else {
-                    self.minmax.data.len()
-                };
-                for b in self.minmax.data[range_start..range_end].iter() {
-                    if b.min < min {
-                        min = b.min
-                    }
-                    if b.max > max {
-                        max = b.max
-                    }
+            canvas.fill_background(x, 0, max_translated, &bg_colors);
+            canvas.fill(x, max_translated, rms_top, &fg_colors);
+            canvas.fill(x, rms_top, rms_bottom, &rms_colors);
+            canvas.fill(x, rms_bottom, min_translated, &fg_colors);
+            canvas.fill_background(x, min_translated, h, &bg_colors);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if none of the RMS band, `Gradient`, clipping color, `Bars`,
+    /// anti-aliasing and blending is used, in which case only the background and
+    /// the foreground are drawn.
+    fn is_plain(&self) -> bool {
+        self.config.get_rms().is_none()
+            && self.config.get_gradient().is_none()
+            && self.config.get_clipping().is_none()
+            && self.config.bars.is_none()
+            && !self.config.anti_aliased
+            && self.config.composition == Composition::Overwrite
+    }
+
+    /// Draws the `w` columns of a plain envelope (see `is_plain`) into `canvas`.
+    fn render_plain(&self, canvas: &mut Canvas, range: TimeRange, w: usize, bg_colors: &[u8; 4], fg_colors: &[u8; 4]) {
+        let h = canvas.shape().1;
+        let translate = |u: f64| -> usize { h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize };
+        for (x, column) in self.columns(range, w).enumerate() {
+            let (top, bottom) = self.config.envelope(column.min.into(), column.max.into(), h);
+            let min_translated = translate(bottom);
+            let max_translated = translate(top);
+            canvas.fill_envelope(x, max_translated, min_translated, bg_colors, fg_colors);
+        }
+    }
+
+    /// Applies `overlay` to the `colors` (background, foreground and RMS)
+    /// of column `x`, where the image starts at sample `begin`.
+    ///
+    /// Returns `false` if the column contains the playhead or a `Marker` in front
+    /// of the waveform, in which case it has been filled entirely
+    /// and nothing else should be drawn.
+    fn apply_overlay(&self, overlay: &Overlay, canvas: &mut Canvas, x: usize, begin: usize, samples_per_pixel: f64, colors: &mut [[u8; 4]; 3]) -> bool {
+        let t0 = begin as f64 + x as f64 * samples_per_pixel;
+        match overlay.apply(t0, t0 + samples_per_pixel, self.sample_rate, colors) {
+            Some(fill) => {
+                let h = canvas.shape().1;
+                canvas.fill(x, 0, h, &fill);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Shrinks the `top` and `bottom` edges of column `x`, which are heights
+    /// measured from the bottom as in `WaveformConfig::envelope`,
+    /// so that the bar it belongs to gets rounded corners.
+    ///
+    /// The corners on the baseline of `Rectified(Baseline::Bottom)` are kept square,
+    /// and the edges are shrunk by whole pixels unless anti-aliased.
+    fn round_corners(&self, bars: &Bars, x: usize, top: f64, bottom: f64) -> (f64, f64) {
+        let width = cmp::max(bars.width, 1);
+        let i = x % (width + bars.gap);
+        if i >= width || top <= bottom {
+            return (top, bottom);
+        }
+
+        let square_bottom = self.config.display_mode == DisplayMode::Rectified(Baseline::Bottom);
+        let length = if square_bottom { top - bottom } else { (top - bottom) / 2f64 };
+        let r = (bars.corner_radius as f64).min(width as f64 / 2f64).min(length);
+        // The distance from the center of the column to the side of the bar.
+        let d = cmp::min(i, width - 1 - i) as f64 + 0.5f64;
+        if d >= r {
+            return (top, bottom);
+        }
+
+        let mut inset = r - (r * r - (r - d) * (r - d)).sqrt();
+        if !self.config.anti_aliased {
+            inset = inset.round();
+        }
+        (top - inset, if square_bottom { bottom } else { bottom + inset })
+    }
+
+    /// Returns an iterator over the `w` columns of an image
+    /// that the samples within `range` are rendered into.
+    pub(crate) fn columns(&self, range: TimeRange, w: usize) -> Columns<'_, T> {
+        let (begin, end) = range.to_sample_tuple(self.sample_rate);
+        Columns::new(&self.minmax.data[..], self.bin_size, begin, end, w)
+    }
+
+    /// Draws the individual samples, one column at a time.
+    ///
+    /// A line interpolated by `WaveformConfig::interpolation` is drawn through them
+    /// if it is set, and stems with dots are drawn on top if `stems` is `true`.
+    fn render_samples(&self, canvas: &mut Canvas, samples: &[T], begin: usize, samples_per_pixel: f64, stems: bool, overlay: Option<&Overlay>) {
+        let (w, h) = canvas.shape();
+        let bg_colors = self.config.get_background().to_bytes();
+        let fg_colors = self.config.get_foreground().to_bytes();
+
+        // The foreground colors of each column, which are `None` for the playhead
+        // cursor so that the stems are not drawn over it.
+        let mut column_fg_colors = vec![Some(fg_colors); w];
+
+        for (x, column_fg_colors) in column_fg_colors.iter_mut().enumerate() {
+            let mut colors = [bg_colors, fg_colors, fg_colors];
+            if let Some(overlay) = overlay {
+                if !self.apply_overlay(overlay, canvas, x, begin, samples_per_pixel, &mut colors) {
+                    *column_fg_colors = None;
+                    continue;
                 }
-                start_bin_idx = range_end;
-            } else {
-                min = T::zero();
-                max = T::zero();
             }
+            let [bg_colors, fg_colors, _] = colors;
+            *column_fg_colors = Some(fg_colors);
 
-            let scale = 1f64 / (self.config.amp_max - self.config.amp_min) * (h as f64);
-            let min_translated: usize = h -
-                cmp::max(
-                    0,
-                    cmp::min(
-                        h as i32,
-                        ((min.into() - self.config.amp_min) * scale).floor() as i32,
-                    ),
-                ) as usize;
-            let max_translated: usize = h -
-                cmp::max(
-                    0,
-                    cmp::min(
-                        h as i32,
-                        ((max.into() - self.config.amp_min) * scale).floor() as i32,
-                    ),
-                ) as usize;
-
-            // Putting this `match` outside for loops improved the speed.
-            match (self.config.get_background(), self.config.get_foreground()) {
-                (Color::Scalar(ba), Color::Scalar(fa)) => {
-                    flipping_three_segment_for!{
-                                for y in 0, max_translated, min_translated, h, {
-                                    pixel!(img[fullw, fullh; offx+x, offy+y]) = ba,
-                                    pixel!(img[fullw, fullh; offx+x, offy+y]) = fa
-                                }
-                            }
-                },
+            canvas.fill_background(x, 0, h, &bg_colors);
 
-                (
-                    Color::Vector3 (br, bg, bb),
-                    Color::Vector3 (fr, fg, fb),
-                ) => {
-                    // Order the RGB values so we can directly
-                    // copy them into the image.
-                    let bg_colors: [u8; 3] = [br, bg, bb];
-                    let fg_colors: [u8; 3] = [fr, fg, fb];
-
-                    // Each `flipping_three_segment_for` macro
-                    // will be expanded into three for loops below.
-                    //
-                    // I could have used just one for loop (and I did once)
-                    // but this made a significant difference in
-                    // the performance.
-                    //
-                    // The `pixel` macro is used to access pixels.
-                    //
-                    // See src/macros/*.rs for the defenitions.
-
-
-                    #[cfg(feature = "rlibc")]
-                    unsafe {
-                        flipping_three_segment_for!{
-                                for y in 0, max_translated, min_translated, h, {
-                                        rlibc::memcpy(
-                                            &mut pixel!(img[fullw, fullh, 3; offx+x, offy+y, 0]) as _,
-                                            &bg_colors[0] as _,
-                                            3
-                                            ),
-                                        rlibc::memcpy(
-                                            &mut pixel!(img[fullw, fullh, 3; offx+x, offy+y, 0]) as _,
-                                            &fg_colors[0] as _,
-                                            3
-                                            )
-                                }
-                            }
-                    }
+            if let Some(interpolation) = self.config.interpolation {
+                // The part of the line within this column is covered by the values on
+                // both of its edges, together with the samples in between them.
+                let t0 = begin as f64 + x as f64 * samples_per_pixel;
+                let t1 = t0 + samples_per_pixel;
+                let v0 = interpolation.interpolate(samples, t0);
+                let v1 = interpolation.interpolate(samples, t1);
+                let mut lo = v0.min(v1);
+                let mut hi = v0.max(v1);
+                let mut k = t0.ceil() as usize;
+                while (k as f64) < t1 && k < samples.len() {
+                    let v: f64 = samples[k].into();
+                    lo = lo.min(v);
+                    hi = hi.max(v);
+                    k += 1;
+                }
+                let (top, bottom) = self.config.envelope(lo, hi, h);
+                self.draw_line(canvas, x, top, bottom, h, &fg_colors);
+            }
 
-                    // A similar implementation is possible without
-                    // the rlibc crate, but it appeared to be
-                    // slightly slower.
-                    #[cfg(not(feature = "rlibc"))]
-                    {
-                        flipping_three_segment_for!{
-                                for y in 0, max_translated, min_translated, h, {
-                                    (&mut pixel!(img[fullw, fullh, 3; offx+x, offy+y, 0 => 4]))
-                                        .write(&bg_colors).unwrap(),
-                                    (&mut pixel!(img[fullw, fullh, 3; offx+x, offy+y, 0 => 4]))
-                                        .write(&fg_colors).unwrap()
-                                }
-                            }
-                    }
+            if stems {
+                // The zero line.
+                let (zero, _) = self.config.envelope(0f64, 0f64, h);
+                self.draw_line(canvas, x, zero, zero, h, &fg_colors);
+            }
+        }
 
-                },
+        if !stems {
+            return;
+        }
 
-                (
-                    Color::Vector4 (br, bg, bb, ba),
-                    Color::Vector4 (fr, fg, fb, fa),
-                ) => {
-
-                    // Order the RGBA values so we can directly
-                    // copy them into the image.
-                    let bg_colors: [u8; 4] = [br, bg, bb, ba];
-                    let fg_colors: [u8; 4] = [fr, fg, fb, fa];
-
-                    // Each `flipping_three_segment_for` macro
-                    // will be expanded into three for loops below.
-                    //
-                    // I could have used just one for loop (and I did once)
-                    // but this made a significant difference in
-                    // the performance.
-                    //
-                    // The `pixel` macro is used to access pixels.
-                    //
-                    // See src/macros/*.rs for the defenitions.
-
-
-                    #[cfg(feature = "rlibc")]
-                    unsafe {
-                        flipping_three_segment_for!{
-                                for y in 0, max_translated, min_translated, h, {
-                                        rlibc::memcpy(
-                                            &mut pixel!(img[fullw, fullh, 4; offx+x, offy+y, 0]) as _,
-                                            &bg_colors[0] as _,
-                                            4
-                                            ),
-                                        rlibc::memcpy(
-                                            &mut pixel!(img[fullw, fullh, 4; offx+x, offy+y, 0]) as _,
-                                            &fg_colors[0] as _,
-                                            4
-                                            )
-                                }
-                            }
-                    }
+        let pixels_per_sample = 1f64 / samples_per_pixel;
+        let radius = self.config.get_stems().map(|s| s.dot_radius).unwrap_or(0) as i64;
+        let translate = |u: f64| -> usize { (h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize).saturating_sub(1) };
+        let last = cmp::min(samples.len(), begin + (w as f64 * samples_per_pixel).ceil() as usize);
+        for (k, s) in samples.iter().enumerate().take(last).skip(begin) {
+            let x = ((k - begin) as f64 * pixels_per_sample).floor() as usize;
+            if x >= w {
+                break;
+            }
+            let v: f64 = (*s).into();
 
-                    // A similar implementation is possible without
-                    // the rlibc crate, but it appeared to be
-                    // slightly slower.
-                    #[cfg(not(feature = "rlibc"))]
-                    {
-                        flipping_three_segment_for!{
-                                for y in 0, max_translated, min_translated, h, {
-                                    (&mut pixel!(img[fullw, fullh, 4; offx+x, offy+y, 0 => 4]))
-                                        .write(&bg_colors).unwrap(),
-                                    (&mut pixel!(img[fullw, fullh, 4; offx+x, offy+y, 0 => 4]))
-                                        .write(&fg_colors).unwrap()
-                                }
-                            }
-                    }
-                },
+            // The stem goes from the zero line to the sample, where the dot is drawn.
+            let (top, bottom) = self.config.envelope(v.min(0f64), v.max(0f64), h);
+            if let Some(fg_colors) = column_fg_colors[x] {
+                self.draw_line(canvas, x, top, bottom, h, &fg_colors);
+            }
 
-                // This case is unreachable because inconsistent
-                // `Color` formats are checked whenever a user
-                // creates a `WaveformConfig`.
-                (_, _) => unreachable!(), 
+            let center = translate(if v >= 0f64 { top } else { bottom }) as i64;
+            for dx in -radius..(radius + 1) {
+                let cx = x as i64 + dx;
+                if cx < 0 || cx >= w as i64 {
+                    continue;
+                }
+                if let Some(fg_colors) = column_fg_colors[cx as usize] {
+                    let dy = ((radius * radius - dx * dx) as f64).sqrt().floor() as i64;
+                    let y0 = cmp::max(0, center - dy) as usize;
+                    let y1 = cmp::min(h as i64, center + dy + 1) as usize;
+                    canvas.fill(cx as usize, y0, y1, &fg_colors);
+                }
             }
         }
+    }
 
-        Ok(())
+    /// Draws a part of a line in column `x`, spanning from `top` to `bottom`,
+    /// which are heights measured from the bottom as in `WaveformConfig::envelope`.
+    ///
+    /// The line is at least one pixel thick.
+    fn draw_line(&self, canvas: &mut Canvas, x: usize, top: f64, bottom: f64, h: usize, color: &[u8; 4]) {
+        if self.config.anti_aliased {
+            let mut y0 = h as f64 - top;
+            let mut y1 = h as f64 - bottom;
+            if y1 - y0 < 1f64 {
+                let center = (y0 + y1) / 2f64;
+                y0 = center - 0.5f64;
+                y1 = center + 0.5f64;
+            }
+            canvas.fill_fractional(x, y0.max(0f64).min(h as f64), y1.max(0f64).min(h as f64), color);
+        } else {
+            // A value `u` pixels above the bottom lies on row `h - 1 - floor(u)`.
+            let translate = |u: f64| -> usize { h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize };
+            let y0 = translate(top).saturating_sub(1);
+            let y1 = cmp::max(translate(bottom), y0 + 1);
+            canvas.fill(x, y0, y1, color);
+        }
     }
 
     pub fn get_bin_size(&self) -> usize {
@@ -359,12 +733,331 @@ impl<T: Sample> BinnedWaveformRenderer<T> {
     pub fn get_sample_rate(&self) -> f64 {
         self.sample_rate
     }
+    pub fn get_minmax(&self) -> &MinMaxPairSequence<T> {
+        &self.minmax
+    }
+}
+
+impl<T: CacheSample> BinnedWaveformRenderer<T> {
+    /// Writes the bins into `writer` in the format of `cache`,
+    /// so that they can be loaded by `read_cache` without the samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `checksum` - A checksum of the samples, such as `cache::checksum`.
+    /// * `writer` - The destination of the cache.
+    pub fn write_cache<W: Write>(&self, checksum: u64, writer: &mut W) -> Result<(), Box<Error>> {
+        write_levels(&[self], checksum, writer)
+    }
+
+    /// Loads bins written by `write_cache` (or `MultiWaveformRenderer::write_cache`,
+    /// in which case the smallest bin size is loaded).
+    ///
+    /// It will raise an error if the data is not a cache of the same version
+    /// and sample type, the checksums are different, or reading from `reader` fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the cache.
+    /// * `config` - See `WaveformConfig`.
+    /// * `checksum` - If given, it must be equal to the one in the cache.
+    pub fn read_cache<R: Read>(reader: &mut R, config: WaveformConfig, checksum: Option<u64>) -> Result<BinnedWaveformRenderer<T>, Box<Error>> {
+        let levels = read_levels(reader, config, checksum)?;
+        match levels.into_iter().min_by_key(|l| l.bin_size) {
+            Some(level) => Ok(level),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+}
+
+/// The bins that a column of an image covers, together with
+/// their aggregated minimum / maximum amplitudes.
+#[derive(Copy, Clone)]
+pub(crate) struct Column<T: Sample> {
+    pub min: T,
+    pub max: T,
+    /// The range of the bins, which is `None` for columns beyond the last bin.
+    pub bins: Option<(usize, usize)>,
+}
+
+/// Accumulates samples into bins one at a time, so that a
+/// `BinnedWaveformRenderer` can be constructed from samples
+/// that are decoded on the fly instead of being in a slice.
+pub(crate) struct Binner<T: Sample> {
+    bin_size: usize,
+    nb_samples: usize,
+    clip_min: f64,
+    clip_max: f64,
+    last: Option<T>,
+    data: Vec<MinMaxPair<T>>,
+    square_sums: Vec<f64>,
+    zero_crossings: Vec<u32>,
+    clipped: Vec<u32>,
+}
+
+impl<T: Sample + FullScale> Binner<T> {
+    /// `bin_size` must not be zero. `config.clipping_threshold` is used here.
+    pub(crate) fn new(bin_size: usize, config: &WaveformConfig) -> Self {
+        let (clip_min, clip_max) = config.clipping_limits::<T>();
+        Self {
+            bin_size,
+            nb_samples: 0,
+            clip_min,
+            clip_max,
+            last: None,
+            data: Vec::new(),
+            square_sums: Vec::new(),
+            zero_crossings: Vec::new(),
+            clipped: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, s: T) {
+        let v: f64 = s.into();
+        // A zero crossing between two samples is counted
+        // in the bin of the latter one.
+        let zero_crossing = match self.last {
+            Some(last) => ((last < T::zero()) != (s < T::zero())) as u32,
+            None => 0,
+        };
+        let clip = (v <= self.clip_min || v >= self.clip_max) as u32;
+        if self.nb_samples % self.bin_size == 0 {
+            self.data.push(MinMaxPair { min: s, max: s });
+            self.square_sums.push(v * v);
+            self.zero_crossings.push(zero_crossing);
+            self.clipped.push(clip);
+        } else {
+            let x = self.data.len() - 1;
+            if s > self.data[x].max {
+                self.data[x].max = s;
+            } else if s < self.data[x].min {
+                self.data[x].min = s;
+            }
+            self.square_sums[x] += v * v;
+            self.zero_crossings[x] += zero_crossing;
+            self.clipped[x] += clip;
+        }
+        self.last = Some(s);
+        self.nb_samples += 1;
+    }
+
+    /// Creates a `BinnedWaveformRenderer` from the bins.
+    ///
+    /// It will raise an error if fewer samples than `bin_size` have been pushed.
+    pub(crate) fn finish(self, sample_rate: f64, config: WaveformConfig) -> Result<BinnedWaveformRenderer<T>, Box<Error>> {
+        if self.bin_size == 0 || self.bin_size > self.nb_samples {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "bin_size".to_string(),
+            }));
+        }
+        Ok(BinnedWaveformRenderer {
+            config,
+            sample_rate,
+            bin_size: self.bin_size,
+            nb_samples: self.nb_samples,
+            minmax: MinMaxPairSequence { data: self.data },
+            square_sums: self.square_sums,
+            zero_crossings: self.zero_crossings,
+            clipped: self.clipped,
+            samples: None,
+        })
+    }
+}
+
+impl<T: Sample> Column<T> {
+    /// Merges with the `Column` right after this one.
+    fn merge(&self, next: &Column<T>) -> Column<T> {
+        match (self.bins, next.bins) {
+            (Some((begin, _)), Some((_, end))) => Column {
+                min: if next.min < self.min { next.min } else { self.min },
+                max: if next.max > self.max { next.max } else { self.max },
+                bins: Some((begin, end)),
+            },
+            (Some(_), None) => *self,
+            (None, _) => *next,
+        }
+    }
+}
+
+/// An iterator over the `Column`s of an image in the bar style.
+///
+/// The columns of each bar are merged into one, which is repeated
+/// for every column of the bar, while those in the gaps have no bins.
+pub(crate) struct BarColumns<'a, T: Sample + 'a> {
+    columns: Columns<'a, T>,
+    bars: Bars,
+    w: usize,
+    x: usize,
+    bar: Option<Column<T>>,
+}
+
+impl<'a, T: Sample> BarColumns<'a, T> {
+    pub fn new(columns: Columns<'a, T>, bars: Bars) -> Self {
+        Self {
+            w: columns.w,
+            columns,
+            bars,
+            x: 0,
+            bar: None,
+        }
+    }
+}
+
+impl<'a, T: Sample> Iterator for BarColumns<'a, T> {
+    type Item = Column<T>;
+
+    fn next(&mut self) -> Option<Column<T>> {
+        if self.x >= self.w {
+            return None;
+        }
+
+        let width = cmp::max(self.bars.width, 1);
+        let i = self.x % (width + self.bars.gap);
+        self.x += 1;
+
+        if i == 0 {
+            self.bar = None;
+            for _ in 0..width {
+                let column = match self.columns.next() {
+                    Some(column) => column,
+                    None => break,
+                };
+                self.bar = Some(match self.bar {
+                    Some(bar) => bar.merge(&column),
+                    None => column,
+                });
+            }
+        }
+
+        if i < width {
+            self.bar
+        } else {
+            self.columns.next().map(|_| Column {
+                min: T::zero(),
+                max: T::zero(),
+                bins: None,
+            })
+        }
+    }
+}
+
+/// An iterator over the `Column`s of an image.
+pub(crate) struct Columns<'a, T: Sample + 'a> {
+    minmax: &'a [MinMaxPair<T>],
+    w: usize,
+    x: usize,
+    bins_per_pixel: f64,
+    offset_bin_idx: usize,
+    start_bin_idx: usize,
+}
+
+impl<'a, T: Sample> Columns<'a, T> {
+    /// # Arguments
+    ///
+    /// * `minmax` - The bins.
+    /// * `bin_size` - The size of the bins.
+    /// * `begin`, `end` - The range of the samples to be rendered.
+    /// * `w` - The number of the columns.
+    pub fn new(minmax: &'a [MinMaxPair<T>], bin_size: usize, begin: usize, end: usize, w: usize) -> Self {
+        let samples_per_pixel = ((end - begin) as f64) / (w as f64);
+        Self {
+            minmax,
+            w,
+            x: 0,
+            bins_per_pixel: samples_per_pixel / (bin_size as f64),
+            offset_bin_idx: begin / bin_size,
+            start_bin_idx: begin / bin_size,
+        }
+    }
+}
+
+impl<'a, T: Sample> Iterator for Columns<'a, T> {
+    type Item = Column<T>;
+
+    fn next(&mut self) -> Option<Column<T>> {
+        if self.x >= self.w {
+            return None;
+        }
+
+        let inc = if ((self.start_bin_idx - self.offset_bin_idx) as f64 + 1f64) / (self.x as f64) < self.bins_per_pixel {
+            self.bins_per_pixel.ceil() as usize
+        } else {
+            self.bins_per_pixel.floor() as usize
+        };
+        self.x += 1;
+
+        if self.start_bin_idx < self.minmax.len() - 1 {
+            let d = &self.minmax[self.start_bin_idx];
+            let mut min = d.min;
+            let mut max = d.max;
+            let range_start = self.start_bin_idx;
+            let range_end = if self.start_bin_idx + inc <= self.minmax.len() {
+                self.start_bin_idx + inc
+            } else {
+                self.minmax.len()
+            };
+            for b in self.minmax[range_start..range_end].iter() {
+                if b.min < min {
+                    min = b.min
+                }
+                if b.max > max {
+                    max = b.max
+                }
+            }
+            self.start_bin_idx = range_end;
+
+            Some(Column {
+                min,
+                max,
+                bins: Some((range_start, range_end)),
+            })
+        } else {
+            Some(Column {
+                min: T::zero(),
+                max: T::zero(),
+                bins: None,
+            })
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::BinnedWaveformRenderer;
     use ::misc::*;
+    use ::zero::Zero;
+    use ::full_scale::FullScale;
+    use std::f64;
+
+    /// A sample type defined outside of the crate.
+    #[derive(Copy, Clone, PartialEq, PartialOrd)]
+    struct Custom(f64);
+    impl From<Custom> for f64 {
+        fn from(s: Custom) -> f64 {
+            s.0
+        }
+    }
+    impl Zero for Custom {
+        fn zero() -> Self {
+            Custom(0f64)
+        }
+    }
+    impl FullScale for Custom {}
+
+    #[test]
+    fn custom_sample() {
+        let samples = [Custom(0.5f64), Custom(-1f64), Custom(0.25f64), Custom(1f64)];
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            2,
+            WaveformConfig::default(),
+        ).unwrap();
+        // The default full scale is `-1..1`.
+        assert_eq!(wfr.count_clipped(TimeRange::Samples(0, 4)), 2);
+    }
 
     #[test]
     fn render_vec_and_write_eq() {
@@ -398,6 +1091,503 @@ mod tests {
         assert_eq!(v1, v2);
     }
 
+    #[test]
+    fn rms() {
+        let samples = [1f64, -1f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_rms(Some(Color::Scalar(128))).unwrap();
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            4,
+            config,
+        ).unwrap();
+
+        // The RMS of the first bin is sqrt(0.5).
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 8)).unwrap();
+        assert_eq!(v, vec![255, 255, 128, 128, 128, 128, 128, 255]);
+
+        assert!(config.set_rms(Some(Color::Vector3(0, 0, 0))).is_err());
+    }
+
+    #[test]
+    fn anti_aliased() {
+        let samples = [0.25f64, -1f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Vector3(200, 100, 0), Color::Vector3(0, 0, 100)).unwrap();
+        config.anti_aliased = true;
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            4,
+            config,
+        ).unwrap();
+
+        // The top edge of the first bin lies in the middle of the second row.
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 4)).unwrap();
+        assert_eq!(v, vec![0, 0, 100, 100, 50, 50, 200, 100, 0, 200, 100, 0]);
+    }
+
+    #[test]
+    fn rectified() {
+        let samples = [0.25f64, -0.5f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.display_mode = DisplayMode::Rectified(Baseline::Bottom);
+        let mut wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            4,
+            config,
+        ).unwrap();
+
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 8)).unwrap();
+        assert_eq!(v, vec![0, 0, 0, 0, 255, 255, 255, 255]);
+
+        wfr.config.display_mode = DisplayMode::Rectified(Baseline::Center);
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 8)).unwrap();
+        assert_eq!(v, vec![0, 0, 255, 255, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn interpolated() {
+        let samples = [0f64, 1f64, 0f64, -1f64, 0f64, 0f64, 0f64, 0f64];
+        let ss = SampleSequence {
+            data: &samples[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.interpolation = Some(Interpolation::Linear);
+        let mut wfr = BinnedWaveformRenderer::new(&ss, 4, config).unwrap();
+        let blocks = wfr.render_vec(TimeRange::Samples(0, 2), (4, 4)).unwrap();
+
+        wfr.keep_samples(samples.to_vec()).unwrap();
+        assert!(wfr.has_samples());
+        let v = wfr.render_vec(TimeRange::Samples(0, 2), (4, 4)).unwrap();
+        assert_ne!(v, blocks);
+
+        // The line rises from 0 to 1 over the first two columns
+        // and goes back down to 0 over the next two.
+        assert_eq!(v, vec![
+            255, 255, 255, 255,
+            255, 0,   0,   255,
+            0,   0,   0,   0,
+            0,   0,   0,   0,
+        ]);
+
+        wfr.config.interpolation = Some(Interpolation::Sinc(4));
+        assert_eq!(wfr.render_vec(TimeRange::Samples(0, 2), (4, 4)).unwrap(), v);
+
+        wfr.discard_samples();
+        assert_eq!(wfr.render_vec(TimeRange::Samples(0, 2), (4, 4)).unwrap(), blocks);
+        assert!(wfr.keep_samples(samples[..4].to_vec()).is_err());
+
+        // Borrowed samples are drawn in the same way as the kept ones.
+        let mut img = vec![0u8; 16];
+        wfr.render_write_with_samples(&samples[..], TimeRange::Samples(0, 2), (0, 0), (4, 4), &mut img[..], (4, 4)).unwrap();
+        assert_eq!(img, v);
+        assert!(!wfr.has_samples());
+        assert!(wfr.render_write_with_samples(&samples[..4], TimeRange::Samples(0, 2), (0, 0), (4, 4), &mut img[..], (4, 4)).is_err());
+    }
+
+    #[test]
+    fn stems() {
+        let samples = [0.5f64, -0.5f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64];
+        let ss = SampleSequence {
+            data: &samples[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_stems(Some(Stems {
+            threshold: 4f64,
+            dot_radius: 0,
+        })).unwrap();
+        for threshold in &[0.5f64, 0f64, f64::NAN] {
+            let mut c = config;
+            assert!(c.set_stems(Some(Stems { threshold: *threshold, dot_radius: 0 })).is_err());
+        }
+        assert_eq!(config.get_stems().unwrap().threshold, 4f64);
+        let mut wfr = BinnedWaveformRenderer::new(&ss, 4, config).unwrap();
+        wfr.keep_samples(samples.to_vec()).unwrap();
+
+        // Four pixels per sample, with a zero line on the fourth row.
+        let v = wfr.render_vec(TimeRange::Samples(0, 2), (8, 8)).unwrap();
+        let f = 255;
+        assert_eq!(v, vec![
+            0, 0, 0, 0, 0, 0, 0, 0,
+            f, 0, 0, 0, 0, 0, 0, 0,
+            f, 0, 0, 0, 0, 0, 0, 0,
+            f, f, f, f, f, f, f, f,
+            0, 0, 0, 0, f, 0, 0, 0,
+            0, 0, 0, 0, f, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+
+        // Below the threshold, the usual envelope is drawn.
+        let blocks = wfr.render_vec(TimeRange::Samples(0, 4), (8, 8)).unwrap();
+        wfr.discard_samples();
+        assert_eq!(wfr.render_vec(TimeRange::Samples(0, 4), (8, 8)).unwrap(), blocks);
+    }
+
+    #[test]
+    fn gradient() {
+        // 10 Hz for the first half, and 200 Hz for the second half.
+        let sample_rate = 1000f64;
+        let mut samples: Vec<f64> = Vec::new();
+        for t in 0..1000 {
+            let f = if t < 500 { 10f64 } else { 200f64 };
+            samples.push(((t as f64) / sample_rate * f * 2f64 * f64::consts::PI + 0.1f64).sin());
+        }
+        let gradient = Gradient::new(&[(200f64, Color::Vector3(0, 0, 255)), (0f64, Color::Vector3(255, 0, 0))]).unwrap();
+        assert_eq!(gradient.color_at(100f64).to_bytes(), [128, 0, 128, 0]);
+        assert!(Gradient::new(&[(0f64, Color::Vector3(0, 0, 0)), (1f64, Color::Scalar(0))]).is_err());
+
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Vector3(255, 255, 255), Color::Vector3(0, 0, 0)).unwrap();
+        config.set_gradient(Some(gradient)).unwrap();
+        assert!(config.set_colors(Color::Scalar(0), Color::Scalar(0)).is_err());
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate,
+            },
+            100,
+            config,
+        ).unwrap();
+
+        // The middle row is inside the envelope for both columns.
+        let v = wfr.render_vec(TimeRange::Samples(0, 1000), (2, 3)).unwrap();
+        let (low, high) = (&v[3 * 2..3 * 2 + 3], &v[3 * 3..3 * 3 + 3]);
+        assert!(low[0] > 200 && low[2] < 50);
+        assert!(high[0] < 50 && high[2] > 200);
+    }
+
+    #[test]
+    fn amplitude_scale() {
+        let samples = [0.1f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.display_mode = DisplayMode::Rectified(Baseline::Bottom);
+        config.set_amplitude_scale(AmplitudeScale::Decibel(-60f64)).unwrap();
+        let mut wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            4,
+            config,
+        ).unwrap();
+
+        // -20 dB is two thirds of the way up from -60 dB.
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 6)).unwrap();
+        assert_eq!(v, vec![0, 0, 255, 255, 255, 255]);
+
+        wfr.config.set_amplitude_scale(AmplitudeScale::SquareRoot).unwrap();
+        let v = wfr.render_vec(TimeRange::Samples(0, 4), (1, 10)).unwrap();
+        assert_eq!(v.iter().filter(|p| **p == 255).count(), 3);
+
+        fn cube(v: f64) -> f64 {
+            v * v * v
+        }
+        assert_eq!(AmplitudeScale::Custom(cube).apply(-2f64, 4f64), -0.5f64);
+        assert_eq!(AmplitudeScale::Decibel(-60f64).apply(0.0001f64, 1f64), 0f64);
+
+        // The floor must be finite and negative.
+        for floor in [0f64, 6f64, f64::NAN, f64::NEG_INFINITY].iter() {
+            assert!(config.set_amplitude_scale(AmplitudeScale::Decibel(*floor)).is_err());
+        }
+        match config.get_amplitude_scale() {
+            AmplitudeScale::Decibel(floor) => assert_eq!(floor, -60f64),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn clipping() {
+        let samples = [i16::MAX, 0, 0, 0, -20000, 0, 0, 0, i16::MIN, i16::MIN, 0, 0];
+        let mut config = WaveformConfig::new(-32768f64, 32767f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_clipping(Some(Color::Scalar(128))).unwrap();
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            4,
+            config,
+        ).unwrap();
+
+        assert_eq!(wfr.count_clipped(TimeRange::Samples(0, 12)), 3);
+        assert_eq!(wfr.count_clipped(TimeRange::Samples(4, 8)), 0);
+        assert_eq!(wfr.rebin(8).unwrap().count_clipped(TimeRange::Samples(0, 12)), 3);
+
+        // Only the first column is clipped.
+        let v = wfr.render_vec(TimeRange::Samples(0, 8), (2, 2)).unwrap();
+        assert_eq!(v, vec![128, 0, 0, 255]);
+
+        let samples = [0.5f64, 0f64, -0.9f64, 0f64];
+        config.clipping_threshold = 0.9f64;
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            1,
+            config,
+        ).unwrap();
+        assert_eq!(wfr.count_clipped(TimeRange::Samples(0, 4)), 1);
+        assert!(config.set_clipping(Some(Color::Vector3(0, 0, 0))).is_err());
+    }
+
+    #[test]
+    fn bars() {
+        let samples = [1f64, -1f64, 0.5f64, -0.5f64];
+        let samples: Vec<f64> = samples.iter().cycle().take(32).cloned().collect();
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.bars = Some(Bars {
+            width: 3,
+            gap: 1,
+            corner_radius: 0,
+            aggregation: Aggregation::Peak,
+        });
+        let mut wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            2,
+            config,
+        ).unwrap();
+
+        let f = 255;
+        let v = wfr.render_vec(TimeRange::Samples(0, 16), (8, 2)).unwrap();
+        assert_eq!(v, vec![
+            f, f, f, 0, f, f, f, 0,
+            f, f, f, 0, f, f, f, 0,
+        ]);
+
+        // The RMS is sqrt(0.625), which is about 0.79 and covers 7 rows.
+        config.bars = Some(Bars {
+            width: 4,
+            gap: 0,
+            corner_radius: 0,
+            aggregation: Aggregation::Rms,
+        });
+        wfr.config = config;
+        let v = wfr.render_vec(TimeRange::Samples(0, 16), (4, 10)).unwrap();
+        assert_eq!(v.iter().filter(|p| **p == 255).count(), 4 * 7);
+
+        // The outer columns lose a pixel at the top.
+        config.display_mode = DisplayMode::Rectified(Baseline::Bottom);
+        config.bars = Some(Bars {
+            width: 6,
+            gap: 0,
+            corner_radius: 2,
+            aggregation: Aggregation::Peak,
+        });
+        wfr.config = config;
+        let v = wfr.render_vec(TimeRange::Samples(0, 12), (6, 4)).unwrap();
+        assert_eq!(v, vec![
+            0, f, f, f, f, 0,
+            f, f, f, f, f, f,
+            f, f, f, f, f, f,
+            f, f, f, f, f, f,
+        ]);
+    }
+
+    #[test]
+    fn overlay() {
+        let samples = [1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64];
+        let config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            2,
+            config,
+        ).unwrap();
+
+        let mut overlay = Overlay::new(Color::Scalar(50), Color::Scalar(100), Color::Scalar(200)).unwrap();
+        overlay.playhead = Some(TimeRange::Samples(2, 2));
+        overlay.selection = Some(TimeRange::Samples(4, 8));
+
+        // Played, cursor, and two selected columns.
+        let v = wfr.render_vec_with_overlay(TimeRange::Samples(0, 8), (4, 2), &overlay).unwrap();
+        assert_eq!(v, vec![100, 50, 228, 228, 0, 50, 100, 100]);
+
+        let overlay = Overlay::new(Color::Vector3(0, 0, 0), Color::Vector3(0, 0, 0), Color::Vector3(0, 0, 0)).unwrap();
+        assert!(wfr.render_vec_with_overlay(TimeRange::Samples(0, 8), (4, 2), &overlay).is_none());
+        assert!(Overlay::new(Color::Scalar(0), Color::Scalar(0), Color::Vector3(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn annotations() {
+        let samples = [1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64];
+        let config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        let wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            2,
+            config,
+        ).unwrap();
+
+        let c = Color::Scalar(0);
+        let mut overlay = Overlay::new(c, c, c).unwrap();
+        overlay.markers.push(Marker { position: TimeRange::Samples(0, 0), color: Color::Scalar(30), layer: Layer::Behind });
+        overlay.markers.push(Marker { position: TimeRange::Samples(2, 2), color: Color::Scalar(40), layer: Layer::InFront });
+        overlay.regions.push(Region { range: TimeRange::Samples(4, 6), color: Color::Scalar(200), opacity: 0.5f64, layer: Layer::Behind });
+        overlay.regions.push(Region { range: TimeRange::Samples(6, 8), color: Color::Scalar(0), opacity: 0.5f64, layer: Layer::InFront });
+
+        let v = wfr.render_vec_with_overlay(TimeRange::Samples(0, 8), (4, 2), &overlay).unwrap();
+        assert_eq!(v, vec![255, 40, 255, 128, 30, 40, 100, 0]);
+
+        overlay.markers.push(Marker { position: TimeRange::Samples(0, 0), color: Color::Vector3(0, 0, 0), layer: Layer::Behind });
+        assert!(wfr.render_vec_with_overlay(TimeRange::Samples(0, 8), (4, 2), &overlay).is_none());
+    }
+
+    #[test]
+    fn composition() {
+        let samples = [1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Vector3(100, 100, 100), Color::Vector3(0, 0, 0)).unwrap();
+        let render = |config: WaveformConfig, fill: u8| {
+            let wfr = BinnedWaveformRenderer::new(
+                &SampleSequence {
+                    data: &samples[..],
+                    sample_rate: 44100f64,
+                },
+                2,
+                config,
+            ).unwrap();
+            let l = config.get_background().bytes_per_pixel();
+            let mut img = vec![fill; 4 * 2 * l];
+            wfr.render_write(TimeRange::Samples(0, 8), (0, 0), (4, 2), &mut img[..], (4, 2)).unwrap();
+            (img[0], img[4 * l])
+        };
+
+        assert_eq!(render(config, 50), (100, 0));
+        config.composition = Composition::Blend { mode: BlendMode::Normal, premultiplied: false };
+        assert_eq!(render(config, 50), (100, 50));
+        config.composition = Composition::Blend { mode: BlendMode::Additive, premultiplied: false };
+        assert_eq!(render(config, 50), (150, 50));
+        config.composition = Composition::Blend { mode: BlendMode::Multiply, premultiplied: false };
+        assert_eq!(render(config, 50), (20, 50));
+
+        // Half transparent.
+        config.set_colors(Color::Vector4(0, 0, 0, 0), Color::Vector4(100, 100, 100, 128)).unwrap();
+        config.composition = Composition::Blend { mode: BlendMode::Normal, premultiplied: false };
+        assert_eq!(render(config, 255), (177, 255));
+        config.composition = Composition::Blend { mode: BlendMode::Normal, premultiplied: true };
+        assert_eq!(render(config, 255), (227, 255));
+    }
+
+    #[test]
+    fn vertical() {
+        let tr = TimeRange::Samples(0, 4000);
+        let (width, height) = (100, 30);
+        let mut samples: Vec<f64> = Vec::new();
+        for t in 0u32..4410u32 {
+            samples.push(((t as f64) * 0.01f64 * 2f64 * f64::consts::PI).sin());
+        }
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Vector3(255, 0, 0), Color::Vector3(0, 0, 255)).unwrap();
+        let mut wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            10,
+            config,
+        ).unwrap();
+        let horizontal = wfr.render_vec(tr, (width, height)).unwrap();
+
+        config.orientation = Orientation::Vertical;
+        wfr.config = config;
+        let (offx, offy) = (3, 5);
+        let (fullw, fullh) = (height + 4, width + 7);
+        let mut vertical = vec![0u8; fullw * fullh * 3];
+        wfr.render_write(tr, (offx, offy), (height, width), &mut vertical[..], (fullw, fullh)).unwrap();
+
+        for x in 0..width {
+            for y in 0..height {
+                let h = (y * width + x) * 3;
+                let v = ((offy + x) * fullw + offx + height - 1 - y) * 3;
+                assert_eq!(horizontal[h..h + 3], vertical[v..v + 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_fast_path() {
+        let tr = TimeRange::Samples(0, 4000);
+        let mut samples: Vec<f64> = Vec::new();
+        for t in 0u32..4410u32 {
+            samples.push(((t as f64) * 0.01f64 * 2f64 * f64::consts::PI).sin() * 0.6f64 + 0.2f64);
+        }
+        let colors = [
+            (Color::Scalar(255), Color::Scalar(0)),
+            (Color::Vector3(255, 0, 0), Color::Vector3(0, 0, 255)),
+            (Color::Vector4(255, 0, 0, 255), Color::Vector4(0, 0, 255, 128)),
+        ];
+        for &(fg, bg) in colors.iter() {
+            for orientation in [Orientation::Horizontal, Orientation::Vertical].iter() {
+                let mut config = WaveformConfig::new(-1f64, 1f64, fg, bg).unwrap();
+                config.orientation = *orientation;
+                let mut wfr = BinnedWaveformRenderer::new(
+                    &SampleSequence {
+                        data: &samples[..],
+                        sample_rate: 44100f64,
+                    },
+                    10,
+                    config,
+                ).unwrap();
+                let (fullw, fullh) = (37, 41);
+                let mut plain = vec![7u8; fullw * fullh * 4];
+                wfr.render_write(tr, (3, 5), (30, 33), &mut plain[..], (fullw, fullh)).unwrap();
+
+                // Highlighting clipped columns in the foreground color changes nothing
+                // but disables the fast path.
+                config.set_clipping(Some(fg)).unwrap();
+                wfr.config = config;
+                let mut general = vec![7u8; fullw * fullh * 4];
+                wfr.render_write(tr, (3, 5), (30, 33), &mut general[..], (fullw, fullh)).unwrap();
+
+                assert_eq!(plain, general);
+            }
+        }
+    }
+
+    #[test]
+    fn rebin_eq_new() {
+        let mut samples: Vec<f64> = Vec::new();
+        for t in 0u32..1005u32 {
+            samples.push(((t as f64) * 0.01f64 * 2f64 * f64::consts::PI).sin());
+        }
+        let ss = SampleSequence {
+            data: &samples[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_rms(Some(Color::Scalar(128))).unwrap();
+        let wfr = BinnedWaveformRenderer::new(&ss, 10, config).unwrap();
+        let rebinned = wfr.rebin(50).unwrap();
+        let direct = BinnedWaveformRenderer::new(&ss, 50, config).unwrap();
+
+        assert_eq!(rebinned.minmax.data.len(), direct.minmax.data.len());
+        for (a, b) in rebinned.minmax.data.iter().zip(direct.minmax.data.iter()) {
+            assert_eq!(a.min, b.min);
+            assert_eq!(a.max, b.max);
+        }
+        for (a, b) in rebinned.square_sums.iter().zip(direct.square_sums.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+        assert_eq!(rebinned.zero_crossings, direct.zero_crossings);
+        assert!(wfr.rebin(55).is_err());
+    }
+
     #[test]
     fn markers() {
         let c = Color::Scalar(0);
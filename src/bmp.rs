@@ -0,0 +1,147 @@
+//! A writer of the BMP format.
+
+use std::error::Error;
+use std::io::Write;
+
+use error::InvalidSizeError;
+
+/// Writes `img` into `writer` as an uncompressed BMP image.
+///
+/// The pixel format depends on `bytes_per_pixel`,
+/// which is 1, 3 or 4 as in `Color::bytes_per_pixel`.
+///
+/// * 1 (`Color::Scalar`) - 8 bits per pixel with a grayscale palette.
+/// * 3 (`Color::Vector3`) - 24 bits per pixel, with the values regarded as RGB.
+/// * 4 (`Color::Vector4`) - 32 bits per pixel, with the values regarded as RGBA.
+///   A `BITMAPV4HEADER` is used so that the alpha values are kept.
+///
+/// It will raise an error if
+///
+/// * the area of `shape` is equal to zero.
+/// * `bytes_per_pixel` is not one of the above.
+/// * the length of `img` is not equal to `shape.0 * shape.1 * bytes_per_pixel`.
+/// * writing into `writer` fails.
+///
+/// # Arguments
+///
+/// * `img` - The pixels laid out as in `render_vec`, or a whole image
+///           that has been written into by `render_write`.
+/// * `shape` - The `(width, height)` of `img` in pixels.
+/// * `bytes_per_pixel` - The number of bytes each pixel occupies.
+/// * `writer` - The destination of the image.
+pub fn write_bmp<W: Write>(img: &[u8], shape: (usize, usize), bytes_per_pixel: usize, writer: &mut W) -> Result<(), Box<Error>> {
+    let (w, h) = shape;
+    if w == 0 || h == 0 || w > 0x7fff_ffff || h > 0x7fff_ffff {
+        return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
+    }
+    let (info_size, palette_size) = match bytes_per_pixel {
+        1 => (40, 256 * 4),
+        3 => (40, 0),
+        4 => (108, 0),
+        _ => return Err(Box::new(InvalidSizeError{var_name: "bytes_per_pixel".to_string()})),
+    };
+    if img.len() != w * h * bytes_per_pixel {
+        return Err(Box::new(InvalidSizeError{var_name: "img".to_string()}));
+    }
+
+    // Each row is padded to a multiple of 4 bytes.
+    let row = w * bytes_per_pixel;
+    let stride = row.div_ceil(4) * 4;
+    let offset = 14 + info_size + palette_size;
+    let file_size = offset + stride * h;
+
+    // BITMAPFILEHEADER
+    let mut header: Vec<u8> = Vec::with_capacity(offset);
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&le32(file_size as u32));
+    header.extend_from_slice(&[0, 0, 0, 0]);
+    header.extend_from_slice(&le32(offset as u32));
+
+    // BITMAPINFOHEADER
+    header.extend_from_slice(&le32(info_size as u32));
+    header.extend_from_slice(&le32(w as u32));
+    // A positive height means the rows are stored from the bottom.
+    header.extend_from_slice(&le32(h as u32));
+    header.extend_from_slice(&[1, 0]);
+    header.extend_from_slice(&[(bytes_per_pixel * 8) as u8, 0]);
+    // BI_BITFIELDS for 32 bits per pixel, and BI_RGB otherwise.
+    header.extend_from_slice(&le32(if bytes_per_pixel == 4 { 3 } else { 0 }));
+    header.extend_from_slice(&le32((stride * h) as u32));
+    // 72 DPI.
+    header.extend_from_slice(&le32(2835));
+    header.extend_from_slice(&le32(2835));
+    header.extend_from_slice(&le32(if bytes_per_pixel == 1 { 256 } else { 0 }));
+    header.extend_from_slice(&le32(0));
+
+    if bytes_per_pixel == 4 {
+        // The rest of BITMAPV4HEADER: the RGBA masks,
+        // the sRGB color space and the unused endpoints and gamma values.
+        header.extend_from_slice(&le32(0x00ff_0000));
+        header.extend_from_slice(&le32(0x0000_ff00));
+        header.extend_from_slice(&le32(0x0000_00ff));
+        header.extend_from_slice(&le32(0xff00_0000));
+        header.extend_from_slice(b"BGRs");
+        header.extend_from_slice(&[0u8; 48]);
+    }
+    if bytes_per_pixel == 1 {
+        for v in 0..256 {
+            header.extend_from_slice(&[v as u8, v as u8, v as u8, 0]);
+        }
+    }
+    writer.write_all(&header)?;
+
+    // The values are stored in the BGR(A) order.
+    let mut buf = vec![0u8; stride];
+    for r in img.chunks(row).rev() {
+        match bytes_per_pixel {
+            1 => buf[..row].copy_from_slice(r),
+            _ => for (dst, src) in buf.chunks_mut(bytes_per_pixel).zip(r.chunks(bytes_per_pixel)) {
+                dst[0] = src[2];
+                dst[1] = src[1];
+                dst[2] = src[0];
+                if bytes_per_pixel == 4 {
+                    dst[3] = src[3];
+                }
+            },
+        }
+        writer.write_all(&buf)?;
+    }
+
+    Ok(())
+}
+
+fn le32(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bmp() {
+        // 2 x 2 RGB, which is padded to 8 bytes per row.
+        let img = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut v: Vec<u8> = Vec::new();
+        write_bmp(&img, (2, 2), 3, &mut v).unwrap();
+        assert_eq!(v.len(), 54 + 16);
+        assert_eq!(&v[0..2], b"BM");
+        assert_eq!(&v[2..6], &[70, 0, 0, 0]);
+        assert_eq!(&v[10..14], &[54, 0, 0, 0]);
+        assert_eq!(&v[28..30], &[24, 0]);
+        assert_eq!(&v[54..], &[9, 8, 7, 12, 11, 10, 0, 0, 3, 2, 1, 6, 5, 4, 0, 0]);
+
+        let mut v: Vec<u8> = Vec::new();
+        write_bmp(&[10, 20, 30, 40], (1, 1), 4, &mut v).unwrap();
+        assert_eq!(v.len(), 14 + 108 + 4);
+        assert_eq!(&v[v.len() - 4..], &[30, 20, 10, 40]);
+
+        let mut v: Vec<u8> = Vec::new();
+        write_bmp(&[10, 20], (1, 2), 1, &mut v).unwrap();
+        assert_eq!(v.len(), 14 + 40 + 1024 + 8);
+        assert_eq!(&v[v.len() - 8..], &[20, 0, 0, 0, 10, 0, 0, 0]);
+
+        assert!(write_bmp(&[10, 20], (1, 1), 1, &mut Vec::new()).is_err());
+        assert!(write_bmp(&[10, 20], (1, 1), 2, &mut Vec::new()).is_err());
+    }
+}
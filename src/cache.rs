@@ -0,0 +1,365 @@
+//! A versioned binary format that caches the bins of renderers,
+//! so that they do not have to be calculated from the samples again.
+//!
+//! All values are little-endian, and every section starts at a multiple of
+//! 8 bytes from the beginning of the file.
+//!
+//! * Header (48 bytes)
+//!   * Magic number `b"WFPC"`
+//!   * Format version (`u32`), which is `VERSION`
+//!   * Sample type (`u32`), see `CacheSample::tag`
+//!   * Number of levels (`u32`)
+//!   * Sample rate (`f64`)
+//!   * Number of samples (`u64`)
+//!   * Checksum of the source samples (`u64`), see `checksum`
+//!   * Reserved (`u64`)
+//! * Level table, with the following entry (24 bytes) for each bin size
+//!   * Bin size (`u64`)
+//!   * Number of bins (`u64`)
+//!   * Offset of the level from the beginning of the file (`u64`)
+//! * Levels, each of which consists of the following arrays,
+//!   padded to multiples of 8 bytes
+//!   * Min / max values of the sample type, interleaved
+//!   * Sums of squares (`f64`)
+//!   * Numbers of zero crossings (`u32`)
+//!   * Numbers of clipped samples (`u32`)
+
+use std::error::Error;
+use std::io::{Read, Write};
+
+use error::{InvalidDataError, InvalidSizeError};
+use misc::*;
+use binned::BinnedWaveformRenderer;
+
+/// The version of the format written by this crate.
+pub const VERSION: u32 = 1;
+
+const MAGIC: &[u8; 4] = b"WFPC";
+pub(crate) const HEADER_SIZE: usize = 48;
+pub(crate) const LEVEL_ENTRY_SIZE: usize = 24;
+
+/// A `Sample` that can be stored in a cache.
+pub trait CacheSample: Sample {
+    /// The identifier of the type in the header.
+    fn tag() -> u32;
+    /// The number of bytes a value occupies.
+    fn size() -> usize;
+    /// Appends the little-endian bytes of this value to `out`.
+    fn write_le(&self, out: &mut Vec<u8>);
+    /// Reads a value from the first `size()` bytes of `bytes` in little endian.
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_cache_sample {
+    ($t:ty, $tag:expr, $size:expr) => {
+        impl CacheSample for $t {
+            fn tag() -> u32 {
+                $tag
+            }
+            fn size() -> usize {
+                $size
+            }
+            fn write_le(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn read_le(bytes: &[u8]) -> Self {
+                let mut b = [0u8; $size];
+                b.copy_from_slice(&bytes[..$size]);
+                <$t>::from_le_bytes(b)
+            }
+        }
+    };
+}
+
+impl_cache_sample!(i8, 1, 1);
+impl_cache_sample!(u8, 2, 1);
+impl_cache_sample!(i16, 3, 2);
+impl_cache_sample!(u16, 4, 2);
+impl_cache_sample!(i32, 5, 4);
+impl_cache_sample!(u32, 6, 4);
+impl_cache_sample!(f32, 7, 4);
+impl_cache_sample!(f64, 8, 8);
+
+/// Calculates a checksum (64-bit FNV-1a) of `samples`, which can be stored in a cache
+/// to tell whether it was made from the same samples.
+pub fn checksum<T: Sample>(samples: &[T]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for s in samples {
+        let v: f64 = (*s).into();
+        for b in v.to_bits().to_le_bytes().iter() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Rounds `n` up to a multiple of 8.
+pub(crate) fn pad(n: usize) -> usize {
+    n.div_ceil(8) * 8
+}
+
+/// The offsets of the arrays of a level from its beginning,
+/// and the size of the level in bytes.
+pub(crate) struct LevelLayout {
+    pub square_sums: usize,
+    pub zero_crossings: usize,
+    pub clipped: usize,
+    pub size: usize,
+}
+
+impl LevelLayout {
+    pub fn new<T: CacheSample>(nb_bins: usize) -> Self {
+        let square_sums = pad(nb_bins * 2 * T::size());
+        let zero_crossings = square_sums + nb_bins * 8;
+        let clipped = zero_crossings + pad(nb_bins * 4);
+        Self {
+            square_sums,
+            zero_crossings,
+            clipped,
+            size: clipped + pad(nb_bins * 4),
+        }
+    }
+}
+
+/// Writes the bins of `levels` into `writer` in the cache format.
+///
+/// It will raise an error if `levels` is empty, its elements differ in their
+/// sample rates or numbers of samples, or writing into `writer` fails.
+pub(crate) fn write_levels<T: CacheSample, W: Write>(levels: &[&BinnedWaveformRenderer<T>], checksum: u64, writer: &mut W) -> Result<(), Box<Error>> {
+    let first = match levels.first() {
+        Some(first) => first,
+        None => return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+    };
+    if levels.iter().any(|l| l.sample_rate != first.sample_rate || l.nb_samples != first.nb_samples) {
+        return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()}));
+    }
+
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE + LEVEL_ENTRY_SIZE * levels.len());
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&T::tag().to_le_bytes());
+    header.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+    header.extend_from_slice(&first.sample_rate.to_bits().to_le_bytes());
+    header.extend_from_slice(&(first.nb_samples as u64).to_le_bytes());
+    header.extend_from_slice(&checksum.to_le_bytes());
+    header.extend_from_slice(&[0u8; 8]);
+
+    let mut offset = pad(HEADER_SIZE + LEVEL_ENTRY_SIZE * levels.len());
+    for l in levels {
+        let nb_bins = l.minmax.data.len();
+        header.extend_from_slice(&(l.bin_size as u64).to_le_bytes());
+        header.extend_from_slice(&(nb_bins as u64).to_le_bytes());
+        header.extend_from_slice(&(offset as u64).to_le_bytes());
+        offset += LevelLayout::new::<T>(nb_bins).size;
+    }
+    header.resize(pad(header.len()), 0);
+    writer.write_all(&header)?;
+
+    for l in levels {
+        let layout = LevelLayout::new::<T>(l.minmax.data.len());
+        let mut bytes: Vec<u8> = Vec::with_capacity(layout.size);
+        for p in l.minmax.data.iter() {
+            p.min.write_le(&mut bytes);
+            p.max.write_le(&mut bytes);
+        }
+        bytes.resize(layout.square_sums, 0);
+        for v in l.square_sums.iter() {
+            bytes.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        for v in l.zero_crossings.iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.resize(layout.clipped, 0);
+        for v in l.clipped.iter() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.resize(layout.size, 0);
+        writer.write_all(&bytes)?;
+    }
+
+    Ok(())
+}
+
+/// The header and the level table of a cache.
+pub(crate) struct CacheHeader {
+    pub sample_rate: f64,
+    pub nb_samples: usize,
+    /// `(bin size, number of bins, offset)` of each level.
+    pub levels: Vec<(usize, usize, usize)>,
+}
+
+/// Parses the header and the level table at the beginning of `bytes`,
+/// and checks that the levels fit in `len` bytes.
+///
+/// It will raise an error if the version or the sample type are different,
+/// or `checksum` is given and different from the stored one.
+pub(crate) fn read_header<T: CacheSample>(bytes: &[u8], len: usize, checksum: Option<u64>) -> Result<CacheHeader, Box<Error>> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err(invalid("not a peak cache"));
+    }
+    if u32_at(bytes, 4) != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    if u32_at(bytes, 8) != T::tag() {
+        return Err(invalid("the sample type is different"));
+    }
+    if let Some(checksum) = checksum {
+        if u64_at(bytes, 32) != checksum {
+            return Err(invalid("the checksum is different"));
+        }
+    }
+
+    let nb_samples = u64_at(bytes, 24) as usize;
+    let nb_levels = u32_at(bytes, 12) as usize;
+    if bytes.len() < HEADER_SIZE + LEVEL_ENTRY_SIZE * nb_levels {
+        return Err(invalid("the level table is too short"));
+    }
+    let mut levels = Vec::with_capacity(nb_levels);
+    for i in 0..nb_levels {
+        let entry = HEADER_SIZE + LEVEL_ENTRY_SIZE * i;
+        let bin_size = u64_at(bytes, entry) as usize;
+        let nb_bins = u64_at(bytes, entry + 8) as usize;
+        let offset = u64_at(bytes, entry + 16) as usize;
+        // An upper bound of the end of the level is checked first to avoid overflows.
+        let fits = offset % 8 == 0
+            && nb_bins.checked_mul(2 * T::size() + 16)
+                .and_then(|n| n.checked_add(offset))
+                .and_then(|n| n.checked_add(24))
+                .is_some()
+            && offset + LevelLayout::new::<T>(nb_bins).size <= len;
+        if bin_size == 0 || nb_bins == 0 || !fits {
+            return Err(invalid("a level is out of range"));
+        }
+        // The last bin must contain the last sample, which is the same as
+        // `(nb_bins - 1) * bin_size < nb_samples <= nb_bins * bin_size`.
+        if nb_samples == 0 || (nb_samples - 1) / bin_size + 1 != nb_bins {
+            return Err(invalid("a level is inconsistent with the number of samples"));
+        }
+        levels.push((bin_size, nb_bins, offset));
+    }
+
+    Ok(CacheHeader {
+        sample_rate: f64::from_bits(u64_at(bytes, 16)),
+        nb_samples,
+        levels,
+    })
+}
+
+/// Reads a cache from `reader`, and creates a `BinnedWaveformRenderer`
+/// for each level, in the order they are stored.
+pub(crate) fn read_levels<T: CacheSample, R: Read>(reader: &mut R, config: WaveformConfig, checksum: Option<u64>) -> Result<Vec<BinnedWaveformRenderer<T>>, Box<Error>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let header = read_header::<T>(&bytes, bytes.len(), checksum)?;
+    Ok((0..header.levels.len())
+        .map(|i| read_level(&bytes, &header, i, (0, header.levels[i].1), config))
+        .collect())
+}
+
+/// Creates a `BinnedWaveformRenderer` from the bins from `bins.0` (inclusive)
+/// to `bins.1` (exclusive) of the `level`-th level in `bytes`,
+/// which must have been checked by `read_header`.
+///
+/// Only those bins are read, and the samples before them are left out,
+/// so the result renders the samples from `bins.0 * bin_size` at `0`.
+pub(crate) fn read_level<T: CacheSample>(bytes: &[u8], header: &CacheHeader, level: usize, bins: (usize, usize), config: WaveformConfig) -> BinnedWaveformRenderer<T> {
+    let (bin_size, nb_bins, offset) = header.levels[level];
+    let layout = LevelLayout::new::<T>(nb_bins);
+    let level = &bytes[offset..offset + layout.size];
+    let size = T::size();
+    let (begin, end) = bins;
+    let minmax = (begin..end)
+        .map(|i| MinMaxPair {
+            min: T::read_le(&level[i * 2 * size..]),
+            max: T::read_le(&level[(i * 2 + 1) * size..]),
+        })
+        .collect();
+    let square_sums = (begin..end)
+        .map(|i| f64::from_bits(u64_at(level, layout.square_sums + i * 8)))
+        .collect();
+    let zero_crossings = (begin..end)
+        .map(|i| u32_at(level, layout.zero_crossings + i * 4))
+        .collect();
+    let clipped = (begin..end)
+        .map(|i| u32_at(level, layout.clipped + i * 4))
+        .collect();
+    BinnedWaveformRenderer {
+        config,
+        sample_rate: header.sample_rate,
+        bin_size,
+        nb_samples: header.nb_samples.saturating_sub(begin * bin_size),
+        minmax: MinMaxPairSequence { data: minmax },
+        square_sums,
+        zero_crossings,
+        clipped,
+        samples: None,
+    }
+}
+
+pub(crate) fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&bytes[i..i + 4]);
+    u32::from_le_bytes(b)
+}
+
+pub(crate) fn u64_at(bytes: &[u8], i: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[i..i + 8]);
+    u64::from_le_bytes(b)
+}
+
+fn invalid(reason: &str) -> Box<Error> {
+    Box::new(InvalidDataError {
+        reason: reason.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multi::MultiWaveformRenderer;
+
+    #[test]
+    fn cache() {
+        let mut samples: Vec<i16> = Vec::new();
+        for t in 0..10000 {
+            samples.push((((t as f64) * 0.01f64).sin() * 40000f64).clamp(-32768f64, 32767f64) as i16);
+        }
+        let ss = SampleSequence {
+            data: &samples[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-32768f64, 32767f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_rms(Some(Color::Scalar(128))).unwrap();
+        config.set_clipping(Some(Color::Scalar(64))).unwrap();
+        let mut mwr = MultiWaveformRenderer::new(&ss, &[10, 100, 1000], config).unwrap();
+
+        let sum = checksum(&samples[..]);
+        let mut bytes: Vec<u8> = Vec::new();
+        mwr.write_cache(sum, &mut bytes).unwrap();
+        assert_eq!(bytes.len() % 8, 0);
+
+        let mut loaded = MultiWaveformRenderer::<i16>::read_cache(&mut &bytes[..], config, Some(sum)).unwrap();
+        assert_eq!(loaded.binned.len(), 3);
+        for (w, h) in [(50, 20), (500, 20), (5000, 20)].iter() {
+            let tr = TimeRange::Seconds(0.01f64, 0.2f64);
+            assert_eq!(loaded.render_vec(tr, (*w, *h)), mwr.render_vec(tr, (*w, *h)));
+        }
+        let binned = BinnedWaveformRenderer::<i16>::read_cache(&mut &bytes[..], config, None).unwrap();
+        assert_eq!(binned.get_bin_size(), 10);
+        assert!(!binned.has_samples());
+        assert_eq!(binned.count_clipped(TimeRange::Samples(0, 10000)), mwr.binned[&10].count_clipped(TimeRange::Samples(0, 10000)));
+
+        assert!(MultiWaveformRenderer::<i16>::read_cache(&mut &bytes[..], config, Some(sum + 1)).is_err());
+        assert!(MultiWaveformRenderer::<f32>::read_cache(&mut &bytes[..], config, None).is_err());
+        assert!(MultiWaveformRenderer::<i16>::read_cache(&mut &bytes[..bytes.len() - 8], config, None).is_err());
+        // The number of samples is fewer than the bins of the largest bin size cover.
+        let mut corrupted = bytes.clone();
+        corrupted[24..32].copy_from_slice(&9000u64.to_le_bytes());
+        assert!(MultiWaveformRenderer::<i16>::read_cache(&mut &corrupted[..], config, None).is_err());
+        corrupted[24..32].copy_from_slice(&10001u64.to_le_bytes());
+        assert!(MultiWaveformRenderer::<i16>::read_cache(&mut &corrupted[..], config, None).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+// Internal helper for writing spans of pixels into image buffers.
+
+use std::error::Error;
+use std::cmp;
+use error::InvalidSizeError;
+use misc::{BlendMode, Composition, Orientation, WaveformConfig};
+
+#[cfg(not(feature = "rlibc"))]
+use std::io::Write;
+
+#[cfg(feature = "rlibc")]
+use rlibc;
+
+/// A rectangular part of an image that renderers draw into,
+/// one column at a time.
+///
+/// Coordinates are given along the time axis (`x`) and
+/// the amplitude axis (`y`, where `0` is the maximum amplitude)
+/// regardless of the `Orientation`.
+///
+/// Pixels are either overwritten or blended into following the `Composition`.
+pub struct Canvas<'a> {
+    img: &'a mut [u8],
+    // These are stored along the time and amplitude axes.
+    shape: (usize, usize),
+    full_shape: (usize, usize),
+    offsets: (usize, usize),
+    bytes_per_pixel: usize,
+    orientation: Orientation,
+    composition: Composition,
+}
+
+impl<'a> Canvas<'a> {
+    /// `offsets`, `shape` and `full_shape` are given as `(width, height)`
+    /// of the image in pixels, as in `render_write`.
+    /// The format, the `Orientation` and the `Composition` follow `config`.
+    ///
+    /// It will raise an error if
+    ///
+    /// * the area of the specified `shape` is equal to zero.
+    /// * either the width or height of the `shape` exceeds that of the `full_shape`
+    ///   of `img`.
+    /// * the length of `img` is not long enough to contain the result.
+    pub fn new(
+        img: &'a mut [u8],
+        offsets: (usize, usize),
+        shape: (usize, usize),
+        full_shape: (usize, usize),
+        config: &WaveformConfig,
+    ) -> Result<Self, Box<Error>> {
+        let bytes_per_pixel = config.get_background().bytes_per_pixel();
+        let orientation = config.orientation;
+        let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
+        }
+
+        let (fullw, fullh) = full_shape;
+        if fullw < w || fullh < h {
+            return Err(Box::new(InvalidSizeError{var_name: "shape and/or full_shape".to_string()}));
+        }
+
+        // Check if we have enough bytes in `img`
+        let (offx, offy) = offsets;
+        if (offx + w) * (offy + h) * bytes_per_pixel > img.len() {
+            return Err(Box::new(InvalidSizeError{var_name: "offsets and/or shape".to_string()}));
+        }
+
+        Ok(Self {
+            img,
+            shape: orientation.logical_shape(shape),
+            full_shape: orientation.logical_shape(full_shape),
+            offsets: orientation.logical_shape(offsets),
+            bytes_per_pixel,
+            orientation,
+            composition: config.composition,
+        })
+    }
+
+    /// The lengths of the canvas along the time and amplitude axes.
+    pub fn shape(&self) -> (usize, usize) {
+        self.shape
+    }
+
+    /// Returns the index of the first byte of the first pixel
+    /// of the rows from `y0` to `y1` in column `x`,
+    /// and the distance in bytes between each of those pixels.
+    ///
+    /// In the `Vertical` orientation the maximum amplitude is on the right,
+    /// so the first pixel in the image is the one at `y1 - 1`.
+    fn span(&self, x: usize, y0: usize, y1: usize) -> (usize, usize) {
+        let (fullw, fullh) = self.full_shape;
+        let (offx, offy) = self.offsets;
+        let l = self.bytes_per_pixel;
+
+        // The `pixel_pos` macro is used to locate pixels.
+        //
+        // See src/macros/*.rs for the defenitions.
+        match self.orientation {
+            Orientation::Horizontal => (pixel_pos!(H; fullw, fullh, l; offx+x, offy+y0, 0), fullw * l),
+            Orientation::Vertical => (pixel_pos!(V; fullw, fullh, l; offx+x, offy+(self.shape.1-y1), 0), l),
+        }
+    }
+
+    /// Fills the pixels from row `y0` (inclusive) to row `y1` (exclusive)
+    /// of column `x` with `color`.
+    ///
+    /// `color` must be laid out as in `Color::to_bytes`.
+    pub fn fill(&mut self, x: usize, y0: usize, y1: usize, color: &[u8; 4]) {
+        if y1 <= y0 {
+            return;
+        }
+        debug_assert!(y1 <= self.shape.1);
+
+        let (start, stride) = self.span(x, y0, y1);
+        let l = self.bytes_per_pixel;
+
+        if let Composition::Blend { mode, premultiplied } = self.composition {
+            for i in 0..(y1 - y0) {
+                let idx = start + i * stride;
+                compose(&mut self.img[idx..idx + l], color, 1f64, mode, premultiplied);
+            }
+            return;
+        }
+
+        let img = &mut *self.img;
+
+        match self.bytes_per_pixel {
+            1 => for i in 0..(y1 - y0) {
+                img[start + i * stride] = color[0];
+            },
+
+            // The lengths are passed as constants so that each copy
+            // is compiled into a single store, which made a significant
+            // difference in the performance.
+            3 => copy_pixels(img, start, stride, y1 - y0, color, 3),
+            _ => copy_pixels(img, start, stride, y1 - y0, color, 4),
+        }
+    }
+
+    /// Fills the pixels from row `y0` (inclusive) to row `y1` (exclusive)
+    /// of column `x` with `fg`, and the rest of the column with `bg`.
+    ///
+    /// This is the fast path for plain envelopes, which must only be used
+    /// under `Composition::Overwrite`.
+    pub fn fill_envelope(&mut self, x: usize, y0: usize, y1: usize, bg: &[u8; 4], fg: &[u8; 4]) {
+        debug_assert!(self.composition == Composition::Overwrite);
+        debug_assert!(y0 <= y1 && y1 <= self.shape.1);
+
+        let (fullw, fullh) = self.full_shape;
+        let (offx, offy) = self.offsets;
+        let h = self.shape.1;
+        let img = &mut *self.img;
+
+        // Writes `color` into the pixel at row `y` of the image.
+        macro_rules! put {
+            ($o:ident; $y:expr; $color:expr; 1) => (pixel!(img[$o; fullw, fullh; offx+x, $y]) = $color[0]);
+            ($o:ident; $y:expr; $color:expr; $l:tt) => (copy_pixels(img, pixel_pos!($o; fullw, fullh, $l; offx+x, $y, 0), 0, 1, $color, $l));
+        }
+
+        // Each `flipping_three_segment_for` macro
+        // will be expanded into three for loops below.
+        //
+        // I could have used just one for loop (and I did once)
+        // but this made a significant difference in
+        // the performance.
+        //
+        // The `pixel` macro is used to access pixels.
+        //
+        // See src/macros/*.rs for the defenitions.
+        macro_rules! envelope {
+            (H, $l:tt) => {{
+                flipping_three_segment_for!{
+                    for y in 0, y0, y1, h, {
+                        put!(H; offy+y; bg; $l),
+                        put!(H; offy+y; fg; $l)
+                    }
+                }
+            }};
+            // In the `Vertical` orientation the maximum amplitude is on the right.
+            (V, $l:tt) => {{
+                flipping_three_segment_for!{
+                    for y in 0, y0, y1, h, {
+                        put!(V; offy+(h-1-y); bg; $l),
+                        put!(V; offy+(h-1-y); fg; $l)
+                    }
+                }
+            }};
+        }
+
+        // Putting this `match` outside for loops improved the speed.
+        match (self.orientation, self.bytes_per_pixel) {
+            (Orientation::Horizontal, 1) => envelope!(H, 1),
+            (Orientation::Horizontal, 3) => envelope!(H, 3),
+            (Orientation::Horizontal, _) => envelope!(H, 4),
+            (Orientation::Vertical, 1) => envelope!(V, 1),
+            (Orientation::Vertical, 3) => envelope!(V, 3),
+            (Orientation::Vertical, _) => envelope!(V, 4),
+        }
+    }
+
+    /// Fills the pixels from row `y0` (inclusive) to row `y1` (exclusive)
+    /// of column `x` with the background `color`, unless the background
+    /// is skipped by `Composition::Blend`.
+    pub fn fill_background(&mut self, x: usize, y0: usize, y1: usize, color: &[u8; 4]) {
+        if self.composition == Composition::Overwrite {
+            self.fill(x, y0, y1, color);
+        }
+    }
+
+    /// Blends `color` into the pixel at row `y` of column `x`
+    /// by the ratio `coverage`, which ranges from 0 to 1.
+    pub fn blend(&mut self, x: usize, y: usize, color: &[u8; 4], coverage: f64) {
+        debug_assert!(y < self.shape.1);
+
+        let (start, _) = self.span(x, y, y + 1);
+        let l = self.bytes_per_pixel;
+        if let Composition::Blend { mode, premultiplied } = self.composition {
+            compose(&mut self.img[start..start + l], color, coverage, mode, premultiplied);
+            return;
+        }
+        for (p, c) in self.img[start..start + l].iter_mut().zip(color[0..l].iter()) {
+            *p = (*p as f64 + (*c as f64 - *p as f64) * coverage).round() as u8;
+        }
+    }
+
+    /// Fills column `x` with `color` between `top` and `bottom`, which are heights
+    /// measured from the bottom as in `WaveformConfig::envelope`.
+    ///
+    /// If `anti_aliased` is `true`, the edges are blended as in `fill_fractional`.
+    pub fn fill_heights(&mut self, x: usize, top: f64, bottom: f64, color: &[u8; 4], anti_aliased: bool) {
+        let h = self.shape.1;
+        if anti_aliased {
+            let translate = |u: f64| -> f64 { (h as f64 - u).max(0f64).min(h as f64) };
+            self.fill_fractional(x, translate(top), translate(bottom), color);
+        } else {
+            let translate = |u: f64| -> usize { h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize };
+            self.fill(x, translate(top), translate(bottom), color);
+        }
+    }
+
+    /// Fills column `x` with `color` between the fractional rows `y0` and `y1`.
+    ///
+    /// Pixels that are fully covered are overwritten, while those on the
+    /// edges are blended by the covered ratio.
+    pub fn fill_fractional(&mut self, x: usize, y0: f64, y1: f64, color: &[u8; 4]) {
+        if y1 <= y0 {
+            return;
+        }
+
+        let top = y0.floor() as usize;
+        let bottom = y1.floor() as usize;
+        if top == bottom {
+            self.blend(x, top, color, y1 - y0);
+            return;
+        }
+
+        let first_full = y0.ceil() as usize;
+        if first_full > top {
+            self.blend(x, top, color, first_full as f64 - y0);
+        }
+        self.fill(x, first_full, bottom, color);
+        if y1 > bottom as f64 {
+            self.blend(x, bottom, color, y1 - bottom as f64);
+        }
+    }
+}
+
+/// Copies the first `l` bytes of `color` into `n` pixels,
+/// starting at `start` and `stride` bytes apart.
+#[inline(always)]
+fn copy_pixels(img: &mut [u8], start: usize, stride: usize, n: usize, color: &[u8; 4], l: usize) {
+    #[cfg(feature = "rlibc")]
+    unsafe {
+        for i in 0..n {
+            rlibc::memcpy(&mut img[start + i * stride] as _, &color[0] as _, l);
+        }
+    }
+
+    // A similar implementation is possible without
+    // the rlibc crate, but it appeared to be
+    // slightly slower.
+    #[cfg(not(feature = "rlibc"))]
+    {
+        for i in 0..n {
+            let idx = start + i * stride;
+            (&mut img[idx..idx + l]).write_all(&color[0..l]).unwrap();
+        }
+    }
+}
+
+/// Mixes `c0` and `c1` by the ratio `t`, which ranges from 0 (only `c0`)
+/// to 1 (only `c1`).
+///
+/// Both are laid out as in `Color::to_bytes`.
+pub fn mix(c0: &[u8; 4], c1: &[u8; 4], t: f64) -> [u8; 4] {
+    let mut c = [0u8; 4];
+    for (v, (v0, v1)) in c.iter_mut().zip(c0.iter().zip(c1.iter())) {
+        *v = (*v0 as f64 + (*v1 as f64 - *v0 as f64) * t).round() as u8;
+    }
+    c
+}
+
+/// Composites `color` over the pixel `dst` by `mode`, with its alpha value
+/// (if there is one) multiplied by `coverage`.
+///
+/// Both are laid out as in `Color::to_bytes`, and the pixel has an alpha value
+/// only if it is 4 bytes long.
+fn compose(dst: &mut [u8], color: &[u8; 4], coverage: f64, mode: BlendMode, premultiplied: bool) {
+    let l = dst.len();
+    let has_alpha = l == 4;
+    let to_f = |v: u8| v as f64 / 255f64;
+    let to_u8 = |v: f64| (v.clamp(0f64, 1f64) * 255f64).round() as u8;
+
+    // The alpha values of the source and the destination.
+    let sa = if has_alpha { to_f(color[3]) } else { 1f64 } * coverage;
+    let da = if has_alpha { to_f(dst[3]) } else { 1f64 };
+    let nb_colors = if has_alpha { 3 } else { l };
+
+    // In the premultiplied form, every value of the source is scaled by the coverage.
+    let scale = if premultiplied && has_alpha { coverage } else { sa };
+
+    for i in 0..nb_colors {
+        let s = to_f(color[i]);
+        let d = to_f(dst[i]);
+        let v = match (mode, premultiplied && has_alpha) {
+            (BlendMode::Normal, false) => {
+                let a = sa + da * (1f64 - sa);
+                if a > 0f64 { (s * sa + d * da * (1f64 - sa)) / a } else { 0f64 }
+            }
+            (BlendMode::Normal, true) => s * scale + d * (1f64 - sa),
+            (BlendMode::Additive, _) => d + s * scale,
+            (BlendMode::Multiply, false) => d * (1f64 - sa) + d * s * sa,
+            (BlendMode::Multiply, true) => s * scale * d + s * scale * (1f64 - da) + d * (1f64 - sa),
+        };
+        dst[i] = to_u8(v);
+    }
+
+    if has_alpha {
+        dst[3] = to_u8(match mode {
+            BlendMode::Additive => da + sa,
+            _ => sa + da * (1f64 - sa),
+        });
+    }
+}
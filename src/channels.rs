@@ -0,0 +1,362 @@
+use std::error::Error;
+use std::cmp;
+
+use super::misc::*;
+use super::error::*;
+use super::canvas::Canvas;
+use super::multi::MultiWaveformRenderer;
+use super::binned::BinnedWaveformRenderer;
+use super::full_scale::FullScale;
+
+/// A renderer for multichannel (e.g. stereo or 5.1) samples,
+/// which draws the channels in lanes stacked along the amplitude axis,
+/// or on top of each other in a single lane with their colors blended.
+///
+/// Each channel is held by a `MultiWaveformRenderer` with its own `WaveformConfig`.
+pub struct MultichannelWaveformRenderer<T: Sample> {
+    pub channels: Vec<MultiWaveformRenderer<T>>,
+    /// The length of each lane along the amplitude axis in pixels.
+    pub lane_height: usize,
+    /// The gap between two lanes in pixels.
+    pub lane_spacing: usize,
+}
+
+impl<T: Sample> MultichannelWaveformRenderer<T> {
+    /// The constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel, from the top lane to the bottom lane.
+    /// * `bin_sizes` - The sizes of the bins which the min / max values will be binned
+    ///                 into.
+    /// * `configs` - The `WaveformConfig`s of each channel.
+    ///               There must be as many of them as `channels`, and their
+    ///               `Color` formats and `Orientation`s must be consistent with each other.
+    /// * `lane_height` - The length of each lane along the amplitude axis in pixels.
+    /// * `lane_spacing` - The gap between two lanes in pixels.
+    pub fn new(
+        channels: &[SampleSequence<T>],
+        bin_sizes: &[usize],
+        configs: &[WaveformConfig],
+        lane_height: usize,
+        lane_spacing: usize,
+    ) -> Result<Self, Box<Error>>
+    where
+        T: FullScale,
+    {
+        if channels.is_empty() || channels.len() != configs.len() {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "channels and/or configs".to_string(),
+            }));
+        }
+        if lane_height == 0 {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "lane_height".to_string(),
+            }));
+        }
+        let bytes_per_pixel = configs[0].get_background().bytes_per_pixel();
+        if configs.iter().any(|c| c.get_background().bytes_per_pixel() != bytes_per_pixel) {
+            return Err(Box::new(InconsistentFormatError));
+        }
+        if configs.iter().any(|c| c.orientation != configs[0].orientation) {
+            return Err(Box::new(InconsistentFormatError));
+        }
+
+        let mut r = Self {
+            channels: Vec::with_capacity(channels.len()),
+            lane_height,
+            lane_spacing,
+        };
+        for (samples, config) in channels.iter().zip(configs.iter()) {
+            r.channels.push(MultiWaveformRenderer::new(samples, bin_sizes, *config)?);
+        }
+
+        Ok(r)
+    }
+
+    /// The length of all the lanes along the amplitude axis in pixels,
+    /// including the gaps between them.
+    pub fn get_lanes_length(&self) -> usize {
+        let n = self.channels.len();
+        n * self.lane_height + (n - 1) * self.lane_spacing
+    }
+
+    /// Renders an image as a `Vec<u8>`.
+    ///
+    /// `None` will be returned if the area of the specified `shape` is equal to zero
+    /// or it is too small to contain all the lanes.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    pub fn render_vec(&self, range: TimeRange, shape: (usize, usize)) -> Option<Vec<u8>> {
+        let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return None;
+        }
+
+        let mut img = vec![0u8; w * h * self.get_config().get_background().bytes_per_pixel()];
+
+        self.render_write(range, (0, 0), shape, &mut img[..], shape).ok()?;
+
+        Some(img)
+    }
+
+    /// Writes the image into a mutable reference to a slice.
+    ///
+    /// The lanes are laid out following the `Orientation` of the channels,
+    /// and the gaps between them as well as the space after the last lane
+    /// are filled with the background `Color` of the first channel.
+    ///
+    /// It will raise an error if
+    ///
+    /// * the area of the specified `shape` is equal to zero.
+    /// * the length of the `shape` along the amplitude axis is shorter
+    ///   than `get_lanes_length()`.
+    /// * either the width or height of the `shape` exceeds that of the `full_shape`
+    ///   of `img`.
+    /// * the length of `img` is not long enough to contain the result.
+    ///   `(offsets.0 + shape.0) * (offsets.1 + shape.1) * (Bytes per pixel) <= img.len()`
+    ///   must be satisfied.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `offsets` - The `(x-offset, y-offset)` of the part of the `img` that is
+    ///               going to be overwritten in in pixels.
+    ///               Specifies the starting position to write into `img`.
+    /// * `shape` - The `(width, height)` of the part of the `img` that is going
+    ///             to be overwritten in pixels.
+    /// * `img`   - A mutable reference to the slice to write the result into.
+    /// * `full_shape` - The `(width, height)` of the whole `img` in pixels.
+    ///
+    pub fn render_write(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        let config = self.get_config();
+        let orientation = config.orientation;
+        let (w, h) = orientation.logical_shape(shape);
+        let lanes_length = self.get_lanes_length();
+        if h < lanes_length {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "shape".to_string(),
+            }));
+        }
+
+        // The gaps are filled first, after which each lane is rendered
+        // by the `BinnedWaveformRenderer` of its channel.
+        {
+            let mut canvas = Canvas::new(
+                img,
+                offsets,
+                shape,
+                full_shape,
+                &config,
+            )?;
+            let bg_colors = config.get_background().to_bytes();
+            for x in 0..w {
+                for i in 1..self.channels.len() {
+                    let end = i * (self.lane_height + self.lane_spacing);
+                    canvas.fill_background(x, end - self.lane_spacing, end, &bg_colors);
+                }
+                canvas.fill_background(x, lanes_length, h, &bg_colors);
+            }
+        }
+
+        let lane_shape = orientation.logical_shape((w, self.lane_height));
+        for (i, channel) in self.channels.iter().enumerate() {
+            let binned = match channel.get_optimal_binned(range, lane_shape) {
+                Some(binned) => binned,
+                None => return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+            };
+
+            // The lanes run from the maximum amplitude side,
+            // which is on the right in the `Vertical` orientation.
+            let position = match orientation {
+                Orientation::Horizontal => i * (self.lane_height + self.lane_spacing),
+                Orientation::Vertical => h - self.lane_height - i * (self.lane_height + self.lane_spacing),
+            };
+            let (offx, offy) = orientation.logical_shape((0, position));
+            binned.render_write(range, (offsets.0 + offx, offsets.1 + offy), lane_shape, img, full_shape)?;
+        }
+
+        Ok(())
+    }
+
+    /// Renders an image with all the channels in a single lane as a `Vec<u8>`.
+    ///
+    /// `None` will be returned if the area of the specified `shape` is equal to zero.
+    ///
+    /// See `render_write_overlaid`.
+    pub fn render_vec_overlaid(&self, range: TimeRange, shape: (usize, usize)) -> Option<Vec<u8>> {
+        let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return None;
+        }
+
+        let mut img = vec![0u8; w * h * self.get_config().get_background().bytes_per_pixel()];
+
+        self.render_write_overlaid(range, (0, 0), shape, &mut img[..], shape).ok()?;
+
+        Some(img)
+    }
+
+    /// Writes an image with all the channels in a single lane
+    /// into a mutable reference to a slice, in one pass.
+    ///
+    /// Each channel is drawn in its own foreground `Color`, and the pixels
+    /// where the envelopes of several channels overlap are drawn in the average of
+    /// their `Color`s. `lane_height`, `lane_spacing` and `anti_aliased` are not used,
+    /// while the background and the `Orientation` follow the first channel.
+    ///
+    /// It will raise an error in the same cases as `render_write`,
+    /// except that the whole `shape` is used as the lane.
+    ///
+    /// # Arguments
+    ///
+    /// See `render_write`.
+    pub fn render_write_overlaid(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        let mut binned: Vec<&BinnedWaveformRenderer<T>> = Vec::with_capacity(self.channels.len());
+        for channel in self.channels.iter() {
+            match channel.get_optimal_binned(range, shape) {
+                Some(b) => binned.push(b),
+                None => return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+            }
+        }
+        let config = binned[0].config;
+
+        let mut canvas = Canvas::new(
+            img,
+            offsets,
+            shape,
+            full_shape,
+            &config,
+        )?;
+        let (w, h) = config.orientation.logical_shape(shape);
+
+        let bg_colors = config.get_background().to_bytes();
+        let fg_colors: Vec<[u8; 4]> = binned.iter().map(|b| b.config.get_foreground().to_bytes()).collect();
+        let translate = |u: f64| -> usize { h - cmp::max(0, cmp::min(h as i32, u.floor() as i32)) as usize };
+
+        let mut columns: Vec<_> = binned.iter().map(|b| b.columns(range, w)).collect();
+        let mut spans = vec![(0usize, 0usize); binned.len()];
+        let mut edges: Vec<usize> = Vec::with_capacity(binned.len() * 2 + 2);
+        for x in 0..w {
+            for ((span, columns), b) in spans.iter_mut().zip(columns.iter_mut()).zip(binned.iter()) {
+                *span = match columns.next() {
+                    Some(column) => {
+                        let (top, bottom) = b.config.envelope(column.min.into(), column.max.into(), h);
+                        (translate(top), translate(bottom))
+                    }
+                    None => (0, 0),
+                };
+            }
+
+            // The column is split at the edges of every envelope,
+            // so that each part is covered by the same channels throughout.
+            edges.clear();
+            edges.push(0);
+            edges.push(h);
+            for &(y0, y1) in spans.iter() {
+                edges.push(y0);
+                edges.push(y1);
+            }
+            edges.sort();
+            edges.dedup();
+
+            for pair in edges.windows(2) {
+                let (y0, y1) = (pair[0], pair[1]);
+                let mut sum = [0u32; 4];
+                let mut count = 0u32;
+                for (&(top, bottom), colors) in spans.iter().zip(fg_colors.iter()) {
+                    if top <= y0 && y1 <= bottom {
+                        for (s, c) in sum.iter_mut().zip(colors.iter()) {
+                            *s += *c as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    canvas.fill_background(x, y0, y1, &bg_colors);
+                    continue;
+                }
+                let mut colors = [0u8; 4];
+                for (c, s) in colors.iter_mut().zip(sum.iter()) {
+                    *c = ((*s + count / 2) / count) as u8;
+                }
+                canvas.fill(x, y0, y1, &colors);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The `WaveformConfig` of the first channel, which the layout follows.
+    fn get_config(&self) -> WaveformConfig {
+        self.channels[0].binned.values().next().map(|b| b.config).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultichannelWaveformRenderer;
+    use misc::*;
+
+    #[test]
+    fn lanes() {
+        let left = [1f64; 100];
+        let right = [-1f64; 100];
+        let channels = [
+            SampleSequence { data: &left[..], sample_rate: 44100f64 },
+            SampleSequence { data: &right[..], sample_rate: 44100f64 },
+        ];
+        let mut left_config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(1), Color::Scalar(0)).unwrap();
+        left_config.display_mode = DisplayMode::Rectified(Baseline::Bottom);
+        let mut right_config = left_config;
+        right_config.set_colors(Color::Scalar(0), Color::Scalar(2)).unwrap();
+        let mcwr = MultichannelWaveformRenderer::new(&channels, &[10], &[left_config, right_config], 2, 1).unwrap();
+        assert_eq!(mcwr.get_lanes_length(), 5);
+
+        // Two lanes of two rows with a gap between them, followed by an extra row.
+        let v = mcwr.render_vec(TimeRange::Samples(0, 50), (1, 6)).unwrap();
+        assert_eq!(v, vec![1, 1, 0, 2, 2, 0]);
+        assert!(mcwr.render_vec(TimeRange::Samples(0, 50), (1, 4)).is_none());
+
+        let mut vertical = left_config;
+        vertical.orientation = Orientation::Vertical;
+        right_config.orientation = Orientation::Vertical;
+        let mcwr = MultichannelWaveformRenderer::new(&channels, &[10], &[vertical, right_config], 2, 1).unwrap();
+        let v = mcwr.render_vec(TimeRange::Samples(0, 50), (6, 1)).unwrap();
+        assert_eq!(v, vec![0, 2, 2, 0, 1, 1]);
+
+        let scalar = WaveformConfig::new(-1f64, 1f64, Color::Scalar(0), Color::Scalar(0)).unwrap();
+        let vector = WaveformConfig::new(-1f64, 1f64, Color::Vector3(0, 0, 0), Color::Vector3(0, 0, 0)).unwrap();
+        assert!(MultichannelWaveformRenderer::new(&channels, &[10], &[scalar, vector], 2, 1).is_err());
+        assert!(MultichannelWaveformRenderer::new(&channels, &[10], &[scalar], 2, 1).is_err());
+        assert!(MultichannelWaveformRenderer::new(&channels, &[10], &[left_config, right_config], 2, 1).is_err());
+    }
+
+    #[test]
+    fn overlaid() {
+        let before = [0f64, 1f64, 0f64, 1f64, 0f64, 1f64, 0f64, 1f64];
+        let after = [-1f64, 1f64, -1f64, 1f64, -1f64, 1f64, -1f64, 1f64];
+        let channels = [
+            SampleSequence { data: &before[..], sample_rate: 44100f64 },
+            SampleSequence { data: &after[..], sample_rate: 44100f64 },
+        ];
+        let background = Color::Vector3(0, 0, 0);
+        let configs = [
+            WaveformConfig::new(-1f64, 1f64, Color::Vector3(200, 0, 0), background).unwrap(),
+            WaveformConfig::new(-1f64, 1f64, Color::Vector3(0, 0, 100), background).unwrap(),
+        ];
+        let mcwr = MultichannelWaveformRenderer::new(&channels, &[2], &configs, 1, 0).unwrap();
+
+        // The upper half is covered by both channels.
+        let v = mcwr.render_vec_overlaid(TimeRange::Samples(0, 2), (1, 4)).unwrap();
+        assert_eq!(v, vec![
+            100, 0, 50,
+            100, 0, 50,
+            0, 0, 100,
+            0, 0, 100,
+        ]);
+    }
+}
@@ -49,15 +49,24 @@ impl DirectWaveformRenderer {
             minmax.data.push(MinMaxPair { min: min, max: max });
         }
 
+        // Amplitudes are compared after being mapped by the `AmplitudeScale`,
+        // which is done once for each column outside the loops.
+        let amp_min = self.config.scale_amplitude(self.config.amp_min);
+        let amp_max = self.config.scale_amplitude(self.config.amp_max);
+        let scaled: Vec<(f64, f64)> = minmax.data.iter()
+            .map(|p| (self.config.scale_amplitude(p.min.into()), self.config.scale_amplitude(p.max.into())))
+            .collect();
+
         // Unlike BinnedWaveformRenderer, the `match` is outside the `for`s
         // because it's faster this way.
         // I've also tried it in BinnedWaveformRenderer but it didn't make a
         // significant improvement in speed, so it's left that way.
         match (self.config.get_background(), self.config.get_foreground()) {
             (Color::Scalar(ba), Color::Scalar(fa)) => for y in 0..h {
-                let y_translated = ((h - y) as f64) / (h as f64) * (self.config.amp_max - self.config.amp_min) + self.config.amp_min;
+                let y_translated = ((h - y) as f64) / (h as f64) * (amp_max - amp_min) + amp_min;
                 for x in 0..w {
-                    if y_translated < minmax.data[x].min.into() || y_translated > minmax.data[x].max.into() {
+                    let (min, max) = scaled[x];
+                    if y_translated < min || y_translated > max {
                         img[1 * (y * w + x) + 0] = ba;
                     } else {
                         img[1 * (y * w + x) + 0] = fa;
@@ -69,9 +78,10 @@ impl DirectWaveformRenderer {
                 Color::Vector4(br, bg, bb, ba),
                 Color::Vector4(fr, fg, fb, fa),
             ) => for y in 0..h {
-                let y_translated = ((h - y) as f64) / (h as f64) * (self.config.amp_max - self.config.amp_min) + self.config.amp_min;
+                let y_translated = ((h - y) as f64) / (h as f64) * (amp_max - amp_min) + amp_min;
                 for x in 0..w {
-                    if y_translated < minmax.data[x].min.into() || y_translated > minmax.data[x].max.into() {
+                    let (min, max) = scaled[x];
+                    if y_translated < min || y_translated > max {
                         img[4 * (y * w + x) + 0] = br;
                         img[4 * (y * w + x) + 1] = bg;
                         img[4 * (y * w + x) + 2] = bb;
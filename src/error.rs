@@ -35,3 +35,20 @@ impl Error for InconsistentFormatError {
         "Color formats of background and foreground must be consistent."
     }
 }
+
+
+#[derive(Debug)]
+pub struct InvalidDataError {
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid data: {}", self.reason)
+    }
+}
+impl Error for InvalidDataError {
+    fn description(&self) -> &str {
+        "Data in an invalid or unsupported format has been read."
+    }
+}
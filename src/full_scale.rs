@@ -0,0 +1,55 @@
+//! Full scales for each supported type are implemented here.
+//!
+//! Used to detect clipped `Sample`s.
+//!
+//! Renderers that count clipped samples require it in addition to `Sample`.
+//! Other sample types can use the default full scale of `-1..1`
+//! with an empty `impl`.
+
+pub trait FullScale {
+    /// The `(minimum, maximum)` values that a sample can reach
+    /// without clipping.
+    fn full_scale() -> (f64, f64) {
+        (-1f64, 1f64)
+    }
+}
+impl FullScale for f64 {
+    fn full_scale() -> (f64, f64) {
+        (-1f64, 1f64)
+    }
+}
+impl FullScale for f32 {
+    fn full_scale() -> (f64, f64) {
+        (-1f64, 1f64)
+    }
+}
+impl FullScale for i16 {
+    fn full_scale() -> (f64, f64) {
+        (i16::MIN as f64, i16::MAX as f64)
+    }
+}
+impl FullScale for u16 {
+    fn full_scale() -> (f64, f64) {
+        (u16::MIN as f64, u16::MAX as f64)
+    }
+}
+impl FullScale for i32 {
+    fn full_scale() -> (f64, f64) {
+        (i32::MIN as f64, i32::MAX as f64)
+    }
+}
+impl FullScale for u32 {
+    fn full_scale() -> (f64, f64) {
+        (u32::MIN as f64, u32::MAX as f64)
+    }
+}
+impl FullScale for u8 {
+    fn full_scale() -> (f64, f64) {
+        (u8::MIN as f64, u8::MAX as f64)
+    }
+}
+impl FullScale for i8 {
+    fn full_scale() -> (f64, f64) {
+        (i8::MIN as f64, i8::MAX as f64)
+    }
+}
@@ -3,16 +3,32 @@
 #[cfg(feature = "rlibc")]
 extern crate rlibc;
 
+#[cfg(feature = "mmap")]
+extern crate libc;
+
 pub mod error;
 
 pub mod zero;
 
+pub mod full_scale;
+
 pub mod misc;
-pub use misc::{Color, Sample, SampleSequence, TimeRange, WaveformConfig};
+pub use misc::{Aggregation, AmplitudeScale, Bars, Baseline, BlendMode, Color, Composition, DisplayMode, Gradient, Interpolation, Layer, Marker, Orientation, Overlay, Region, Sample, SampleSequence, Stems, TimeRange, WaveformConfig};
 
 #[macro_use]
 mod macros;
 
+mod canvas;
+
+mod svg;
+
+#[cfg(feature = "png")]
+pub mod png;
+
+pub mod netpbm;
+
+pub mod bmp;
+
 pub mod binned;
 pub use binned::BinnedWaveformRenderer;
 
@@ -21,3 +37,26 @@ pub mod direct;
 
 pub mod multi;
 pub use multi::MultiWaveformRenderer;
+
+pub mod bands;
+pub use bands::BandedWaveformRenderer;
+
+pub mod channels;
+pub use channels::MultichannelWaveformRenderer;
+
+pub mod audiowaveform;
+
+pub mod cache;
+
+pub mod pcm;
+
+#[cfg(feature="wav")]
+pub mod wav;
+
+#[cfg(feature="aiff")]
+pub mod aiff;
+
+#[cfg(all(feature = "mmap", unix))]
+pub mod mapped;
+#[cfg(all(feature = "mmap", unix))]
+pub use mapped::MappedWaveformRenderer;
@@ -0,0 +1,215 @@
+//! A renderer that reads the bins from a memory-mapped cache file
+//! (see `cache`) instead of keeping them in memory.
+//!
+//! Only the bins needed for each image are read, so even a huge file
+//! can be opened and rendered at once, as long as it contains bin sizes
+//! that are large enough for the overview.
+
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use libc;
+
+use error::{InvalidDataError, InvalidSizeError};
+use misc::*;
+use binned::BinnedWaveformRenderer;
+use cache::{read_header, read_level, CacheHeader, CacheSample};
+
+/// A read-only memory map of a whole file.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(file: &File) -> Result<Self, Box<Error>> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Err(Box::new(InvalidDataError {
+                reason: "the file is empty".to_string(),
+            }));
+        }
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(Box::new(io::Error::last_os_error()));
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// The map is never written into.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+/// A renderer that reads the bins of a file written by
+/// `MultiWaveformRenderer::write_cache` through a memory map.
+///
+/// Like `MultiWaveformRenderer`, it chooses an appropriate bin size each time
+/// it renders, and reads only the bins within the `TimeRange` from the file.
+///
+/// The file must not be modified while it is opened.
+pub struct MappedWaveformRenderer<T: CacheSample> {
+    pub config: WaveformConfig,
+    map: Mmap,
+    header: CacheHeader,
+    _sample: PhantomData<T>,
+}
+
+impl<T: CacheSample> MappedWaveformRenderer<T> {
+    /// Opens a cache file.
+    ///
+    /// It will raise an error if the file cannot be mapped, or it is not a cache
+    /// of the same version and sample type, or the checksums are different.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the cache file.
+    /// * `config` - See `WaveformConfig`.
+    /// * `checksum` - If given, it must be equal to the one in the cache.
+    pub fn open<P: AsRef<Path>>(path: P, config: WaveformConfig, checksum: Option<u64>) -> Result<Self, Box<Error>> {
+        let file = File::open(path)?;
+        let map = Mmap::new(&file)?;
+        let header = read_header::<T>(map.as_slice(), map.len, checksum)?;
+        if header.levels.is_empty() {
+            return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()}));
+        }
+        Ok(Self {
+            config,
+            map,
+            header,
+            _sample: PhantomData,
+        })
+    }
+
+    /// The bin sizes in the file, in the order they are stored.
+    pub fn get_bin_sizes(&self) -> Vec<usize> {
+        self.header.levels.iter().map(|l| l.0).collect()
+    }
+
+    pub fn get_sample_rate(&self) -> f64 {
+        self.header.sample_rate
+    }
+
+    /// Renders an image as a `Vec<u8>`.
+    ///
+    /// `None` will be returned if the area of the specified `shape` is equal to zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The samples within this `TimeRange` will be rendered.
+    /// * `shape` - The `(width, height)` of the resulting image in pixels.
+    pub fn render_vec(&self, range: TimeRange, shape: (usize, usize)) -> Option<Vec<u8>> {
+        let (w, h) = shape;
+        if w == 0 || h == 0 {
+            return None;
+        }
+
+        let mut img = vec![0u8; w * h * self.config.get_background().bytes_per_pixel()];
+
+        self.render_write(range, (0, 0), shape, &mut img[..], shape).ok()?;
+
+        Some(img)
+    }
+
+    /// Writes the image into a mutable reference to a slice.
+    ///
+    /// See `BinnedWaveformRenderer::render_write`.
+    pub fn render_write(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
+        let (w, h) = self.config.orientation.logical_shape(shape);
+        if w == 0 || h == 0 {
+            return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
+        }
+        let (binned, range) = self.get_binned(range, w);
+        binned.render_write(range, offsets, shape, img, full_shape)
+    }
+
+    /// Reads the bins of the appropriate bin size that `w` columns of
+    /// the samples within `range` cover, and returns them together with
+    /// `range` shifted to the first of those bins.
+    fn get_binned(&self, range: TimeRange, w: usize) -> (BinnedWaveformRenderer<T>, TimeRange) {
+        let (begin, end) = range.to_sample_tuple(self.header.sample_rate);
+        let samples_per_pixel = ((end - begin) as f64) / (w as f64);
+
+        // The largest bin size that does not exceed the samples per pixel,
+        // or the smallest one.
+        let mut levels: Vec<usize> = (0..self.header.levels.len()).collect();
+        levels.sort_by_key(|i| self.header.levels[*i].0);
+        let level = levels.iter()
+            .rev()
+            .find(|i| self.header.levels[**i].0 as f64 <= samples_per_pixel)
+            .unwrap_or(&levels[0]);
+        let (bin_size, nb_bins, _) = self.header.levels[*level];
+
+        // The columns take at most `ceil(bins per pixel)` bins each,
+        // and extra bins are read so that the last column is not regarded
+        // as being beyond the end of the samples.
+        let first = (begin / bin_size).min(nb_bins - 1);
+        let bins_per_pixel = (samples_per_pixel / bin_size as f64).ceil() as usize;
+        let last = first.saturating_add(w.saturating_mul(bins_per_pixel)).saturating_add(2).min(nb_bins);
+
+        let binned = read_level(self.map.as_slice(), &self.header, *level, (first, last), self.config);
+        let shift = first * bin_size;
+        (binned, TimeRange::Samples(begin - shift, end - shift))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use multi::MultiWaveformRenderer;
+
+    #[test]
+    fn mapped() {
+        let mut samples: Vec<f32> = Vec::new();
+        for t in 0..100000 {
+            samples.push(((t as f32) * 0.001f32).sin() * (t as f32 / 100000f32));
+        }
+        let ss = SampleSequence {
+            data: &samples[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_rms(Some(Color::Scalar(128))).unwrap();
+        let mut mwr = MultiWaveformRenderer::new(&ss, &[10, 100, 1000], config).unwrap();
+
+        let path = env::temp_dir().join(format!("waveform-mapped-{}.wfpc", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        mwr.write_cache(7, &mut file).unwrap();
+        drop(file);
+
+        let mapped = MappedWaveformRenderer::<f32>::open(&path, config, Some(7)).unwrap();
+        assert_eq!(mapped.get_bin_sizes(), vec![10, 100, 1000]);
+        for &(range, shape) in [
+            (TimeRange::Samples(0, 100000), (100, 20)),
+            (TimeRange::Samples(12345, 23456), (300, 20)),
+            (TimeRange::Samples(99000, 101000), (50, 20)),
+            (TimeRange::Seconds(0.5f64, 0.6f64), (1000, 20)),
+        ].iter() {
+            assert_eq!(mapped.render_vec(range, shape), mwr.render_vec(range, shape));
+        }
+
+        assert!(MappedWaveformRenderer::<f32>::open(&path, config, Some(8)).is_err());
+        assert!(MappedWaveformRenderer::<f64>::open(&path, config, None).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,10 @@
 use zero::Zero;
-use error::InconsistentFormatError;
+use full_scale::FullScale;
+use error::{InconsistentFormatError, InvalidSizeError};
 use std::error::Error;
+use std::cmp;
+use std::f64;
+use canvas::mix;
 
 /// Color specifiers.
 #[derive(Copy, Clone)]
@@ -13,22 +17,338 @@ pub enum Color {
     Vector4(u8, u8, u8, u8),
 }
 
+impl Color {
+    /// The number of bytes a pixel of this format occupies.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            Color::Scalar(_) => 1,
+            Color::Vector3(..) => 3,
+            Color::Vector4(..) => 4,
+        }
+    }
+
+    /// The values of this `Color` in the order they are laid out in an image.
+    ///
+    /// Only the first `bytes_per_pixel()` values are meaningful.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        match *self {
+            Color::Scalar(a) => [a, 0, 0, 0],
+            Color::Vector3(r, g, b) => [r, g, b, 0],
+            Color::Vector4(r, g, b, a) => [r, g, b, a],
+        }
+    }
+}
+
+/// Orientation specifiers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Orientation {
+    /// Time runs from left to right, and the maximum amplitude is at the top.
+    Horizontal,
+    /// Time runs from top to bottom, and the maximum amplitude is on the right.
+    Vertical,
+}
+
+impl Orientation {
+    /// Converts a `(width, height)` pair into a pair of
+    /// the lengths along the time axis and the amplitude axis.
+    ///
+    /// The conversion is its own inverse, so it can be used the other way around as well.
+    pub fn logical_shape(&self, shape: (usize, usize)) -> (usize, usize) {
+        match *self {
+            Orientation::Horizontal => shape,
+            Orientation::Vertical => (shape.1, shape.0),
+        }
+    }
+}
+
+/// Display mode specifiers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DisplayMode {
+    /// The signed min / max envelope.
+    MinMax,
+    /// `max(|min|, |max|)` drawn from a `Baseline`,
+    /// using the larger of `|amp_min|` and `|amp_max|` as the full scale.
+    Rectified(Baseline),
+}
+
+/// Amplitude scale specifiers, which map amplitudes to positions in images.
+///
+/// Amplitudes are normalized by the full scale, which is the larger of
+/// `|amp_min|` and `|amp_max|`, before they are mapped,
+/// and the signs are kept.
+#[derive(Copy, Clone, Debug)]
+pub enum AmplitudeScale {
+    /// Amplitudes are used as they are.
+    Linear,
+    /// Logarithmic scale in dBFS. Amplitudes at or below the floor,
+    /// which is given in dB and must be negative, are mapped to zero.
+    Decibel(f64),
+    /// The square root of amplitudes.
+    SquareRoot,
+    /// A user-defined curve, which maps normalized amplitudes
+    /// from `-1..1` to `-1..1`.
+    Custom(fn(f64) -> f64),
+}
+
+impl AmplitudeScale {
+    /// Maps the amplitude `v` by this scale.
+    ///
+    /// # Arguments
+    /// * `v` - The amplitude.
+    /// * `full_scale` - The amplitude that corresponds to 0 dBFS,
+    ///                  or `1` after normalization.
+    pub fn apply(&self, v: f64, full_scale: f64) -> f64 {
+        match *self {
+            AmplitudeScale::Linear => v,
+            AmplitudeScale::Decibel(floor) => {
+                if v == 0f64 {
+                    return 0f64;
+                }
+                let db = 20f64 * (v.abs() / full_scale).log10();
+                v.signum() * full_scale * (1f64 - db / floor).max(0f64)
+            }
+            AmplitudeScale::SquareRoot => v.signum() * full_scale * (v.abs() / full_scale).sqrt(),
+            AmplitudeScale::Custom(f) => full_scale * f(v / full_scale),
+        }
+    }
+}
+
+/// How the waveform is composited into the image.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Composition {
+    /// Every pixel is overwritten with either the background or the foreground.
+    Overwrite,
+    /// The background is skipped, and the other `Color`s are composited
+    /// over whatever is already in the image by their alpha values
+    /// (which are regarded as opaque for `Scalar` and `Vector3`).
+    ///
+    /// Anything drawn in place of the background, such as `Layer::Behind`
+    /// annotations of an `Overlay`, is skipped as well.
+    Blend {
+        mode: BlendMode,
+        /// If `true`, both the image and the `Color`s are regarded as
+        /// having their color values premultiplied by their alpha values.
+        premultiplied: bool,
+    },
+}
+
+/// Blend mode specifiers used by `Composition::Blend`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendMode {
+    /// The `Color` is placed over the image ("source over").
+    Normal,
+    /// The `Color` is added to the image.
+    Additive,
+    /// The image is multiplied by the `Color`.
+    Multiply,
+}
+
+/// Where rectified waveforms are drawn from.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Baseline {
+    /// Drawn upwards from the bottom edge.
+    Bottom,
+    /// Mirrored around the center.
+    Center,
+}
+
+/// Interpolation methods used to draw lines through individual samples
+/// when there are fewer samples than pixels.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interpolation {
+    /// Straight lines between samples.
+    Linear,
+    /// Band-limited interpolation by a sinc function with a Lanczos window
+    /// that spans the given number of samples on each side.
+    Sinc(usize),
+}
+
+impl Interpolation {
+    /// Calculates the amplitude at the fractional sample position `t`.
+    ///
+    /// Samples outside of `samples` are regarded as zeros.
+    pub fn interpolate<T: Sample>(&self, samples: &[T], t: f64) -> f64 {
+        let get = |i: i64| -> f64 {
+            if i < 0 || i as usize >= samples.len() {
+                0f64
+            } else {
+                samples[i as usize].into()
+            }
+        };
+        let i = t.floor() as i64;
+        let frac = t - i as f64;
+
+        match *self {
+            Interpolation::Linear => get(i) * (1f64 - frac) + get(i + 1) * frac,
+            Interpolation::Sinc(width) => {
+                if frac == 0f64 {
+                    return get(i);
+                }
+                let a = cmp::max(width, 1) as i64;
+                let sinc = |x: f64| (x * f64::consts::PI).sin() / (x * f64::consts::PI);
+                let mut v = 0f64;
+                for k in (i - a + 1)..(i + a + 1) {
+                    let x = t - k as f64;
+                    v += get(k) * sinc(x) * sinc(x / a as f64);
+                }
+                v
+            }
+        }
+    }
+}
+
+/// Settings of the "lollipop" view, where each sample is drawn
+/// as a dot on a stem from the zero line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Stems {
+    /// The view is used when there are at least this many pixels per sample.
+    /// It must be at least 1, since stems are only drawn when zoomed in
+    /// below one sample per pixel.
+    pub threshold: f64,
+    /// The radius of the dots in pixels.
+    pub dot_radius: usize,
+}
+
+/// Settings of the bar style, where the waveform is drawn as discrete bars
+/// separated by gaps.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Bars {
+    /// The width of each bar along the time axis in pixels.
+    pub width: usize,
+    /// The width of the gaps between bars in pixels.
+    pub gap: usize,
+    /// The radius of the rounded corners of the bars in pixels.
+    pub corner_radius: usize,
+    /// How the bins under each bar are aggregated.
+    pub aggregation: Aggregation,
+}
+
+/// Ways of aggregating the bins under a bar.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Aggregation {
+    /// The minimum / maximum amplitudes.
+    Peak,
+    /// The RMS value, drawn symmetrically around zero.
+    Rms,
+}
+
+/// A gradient of `Color`s that the waveform is colored by,
+/// column by column, depending on the brightness of the sound.
+///
+/// The positions of the stops are estimated frequencies in Hz,
+/// which are derived from the zero-crossing rates of the samples.
+#[derive(Copy, Clone)]
+pub struct Gradient {
+    stops: [(f64, Color); Gradient::MAX_STOPS],
+    len: usize,
+}
+
+impl Gradient {
+    /// The maximum number of stops in a `Gradient`.
+    pub const MAX_STOPS: usize = 8;
+
+    /// The constructor.
+    ///
+    /// # Arguments
+    /// * `stops` - Pairs of positions and `Color`s, in any order.
+    ///             There must be at least one and at most `MAX_STOPS` of them,
+    ///             and the formats of the `Color`s must be consistent.
+    pub fn new(stops: &[(f64, Color)]) -> Result<Self, Box<Error>> {
+        if stops.is_empty() || stops.len() > Self::MAX_STOPS {
+            return Err(Box::new(InvalidSizeError {
+                var_name: "stops".to_string(),
+            }));
+        }
+        for &(_, c) in stops.iter() {
+            WaveformConfig::check_color_consistency(stops[0].1, c)?;
+        }
+
+        let mut g = Self {
+            stops: [(0f64, stops[0].1); Self::MAX_STOPS],
+            len: stops.len(),
+        };
+        g.stops[..stops.len()].copy_from_slice(stops);
+        g.stops[..stops.len()].sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal));
+
+        Ok(g)
+    }
+
+    /// Returns the `Color` at `position`, interpolating linearly between stops.
+    pub fn color_at(&self, position: f64) -> Color {
+        let stops = &self.stops[..self.len];
+        if position <= stops[0].0 {
+            return stops[0].1;
+        }
+        for pair in stops.windows(2) {
+            let ((p0, c0), (p1, c1)) = (pair[0], pair[1]);
+            if position < p1 {
+                let t = (position - p0) / (p1 - p0);
+                let b = mix(&c0.to_bytes(), &c1.to_bytes(), t);
+                return match c0 {
+                    Color::Scalar(_) => Color::Scalar(b[0]),
+                    Color::Vector3(..) => Color::Vector3(b[0], b[1], b[2]),
+                    Color::Vector4(..) => Color::Vector4(b[0], b[1], b[2], b[3]),
+                };
+            }
+        }
+        stops[self.len - 1].1
+    }
+
+    fn format(&self) -> Color {
+        self.stops[0].1
+    }
+}
+
 /// Configurations for image generators.
 ///
 /// It contains the following information:
 ///
-///  * Range of the amplitudes to be rendered
+///  * Range of the amplitudes to be rendered, and the `AmplitudeScale`
 ///  * Foreground and background `Color`s to be used
+///  * An optional `Color` for the RMS band drawn inside the min / max envelope
+///  * Whether the edges of the waveform are anti-aliased
+///  * The `Orientation` of the image
+///  * The `DisplayMode`, i.e. whether the waveform is rectified or not
+///  * The `Interpolation` used when zoomed in below one sample per pixel
+///  * Whether samples are drawn as `Stems` when zoomed in further
+///  * Whether the waveform is drawn as `Bars`
+///  * The `Composition`, i.e. whether the image is overwritten or blended into
+///  * An optional `Gradient` used instead of the foreground `Color`
+///  * An optional warning `Color` for columns that contain clipped samples
 #[derive(Copy, Clone)]
 pub struct WaveformConfig {
     pub amp_min: f64,
     pub amp_max: f64,
+    pub orientation: Orientation,
+    pub display_mode: DisplayMode,
+    /// If this is not `None` and the renderer keeps the samples,
+    /// a line interpolated between the samples is drawn
+    /// whenever there are fewer samples than pixels.
+    pub interpolation: Option<Interpolation>,
+    /// If this is not `None`, the waveform is drawn as bars
+    /// instead of a continuous envelope.
+    pub bars: Option<Bars>,
+    /// If `true`, pixels on the edges of the waveform are blended
+    /// into the background by how much of them is covered.
+    pub anti_aliased: bool,
+    pub composition: Composition,
+    /// Samples that reach this ratio of the full scale of their type
+    /// (see `FullScale`) are counted as clipped. The default is `1`.
+    ///
+    /// It is used when bins are built, so changing it afterwards
+    /// has no effect on existing renderers.
+    pub clipping_threshold: f64,
     foreground: Color,
     background: Color,
+    amplitude_scale: AmplitudeScale,
+    rms: Option<Color>,
+    gradient: Option<Gradient>,
+    clipping: Option<Color>,
+    stems: Option<Stems>,
 }
 
 impl WaveformConfig {
-    fn check_color_consistency(c1: Color, c2: Color) -> Result<(), Box<InconsistentFormatError>> {
+    fn check_color_consistency(c1: Color, c2: Color) -> Result<(), Box<Error>> {
         match c1 {
             Color::Scalar(_) => {
                 if let Color::Scalar(_) = c2 {
@@ -70,8 +390,20 @@ impl WaveformConfig {
         Ok(Self {
             amp_min,
             amp_max,
+            amplitude_scale: AmplitudeScale::Linear,
+            orientation: Orientation::Horizontal,
+            display_mode: DisplayMode::MinMax,
+            interpolation: None,
+            bars: None,
+            anti_aliased: false,
+            composition: Composition::Overwrite,
+            clipping_threshold: 1f64,
             background,
             foreground,
+            rms: None,
+            gradient: None,
+            clipping: None,
+            stems: None,
         })
     }
 
@@ -81,6 +413,21 @@ impl WaveformConfig {
     pub fn get_foreground(&self) -> Color {
         self.foreground
     }
+    pub fn get_amplitude_scale(&self) -> AmplitudeScale {
+        self.amplitude_scale
+    }
+    pub fn get_rms(&self) -> Option<Color> {
+        self.rms
+    }
+    pub fn get_gradient(&self) -> Option<Gradient> {
+        self.gradient
+    }
+    pub fn get_clipping(&self) -> Option<Color> {
+        self.clipping
+    }
+    pub fn get_stems(&self) -> Option<Stems> {
+        self.stems
+    }
 
     /// Sets `Color`s.
     ///
@@ -92,12 +439,152 @@ impl WaveformConfig {
             Err(e) => return Err(e),
             _ => (),
         }
+        if let Some(rms) = self.rms {
+            Self::check_color_consistency(background, rms)?;
+        }
+        if let Some(gradient) = self.gradient {
+            Self::check_color_consistency(background, gradient.format())?;
+        }
+        if let Some(clipping) = self.clipping {
+            Self::check_color_consistency(background, clipping)?;
+        }
 
         self.background = background;
         self.foreground = foreground;
 
         Ok(())
     }
+
+    /// Sets the `AmplitudeScale`.
+    ///
+    /// It is `AmplitudeScale::Linear` by default.
+    ///
+    /// # Arguments
+    /// * `amplitude_scale` - The `AmplitudeScale`, the floor of `Decibel` must be finite and negative.
+    pub fn set_amplitude_scale(&mut self, amplitude_scale: AmplitudeScale) -> Result<(), Box<Error>> {
+        if let AmplitudeScale::Decibel(floor) = amplitude_scale {
+            if !floor.is_finite() || floor >= 0f64 {
+                return Err(Box::new(InvalidSizeError {
+                    var_name: "amplitude_scale".to_string(),
+                }));
+            }
+        }
+
+        self.amplitude_scale = amplitude_scale;
+
+        Ok(())
+    }
+
+    /// Sets the `Color` of the RMS band.
+    ///
+    /// The RMS band is not rendered if it is `None`, which is the default.
+    ///
+    /// # Arguments
+    /// * `rms` - `Color` of the RMS band, format must be consistent with background.
+    pub fn set_rms(&mut self, rms: Option<Color>) -> Result<(), Box<Error>> {
+        if let Some(c) = rms {
+            Self::check_color_consistency(self.background, c)?;
+        }
+
+        self.rms = rms;
+
+        Ok(())
+    }
+
+    /// Sets the `Gradient` that the waveform is colored by
+    /// instead of the foreground `Color`.
+    ///
+    /// The foreground `Color` is used if it is `None`, which is the default.
+    ///
+    /// # Arguments
+    /// * `gradient` - The `Gradient`, format must be consistent with background.
+    pub fn set_gradient(&mut self, gradient: Option<Gradient>) -> Result<(), Box<Error>> {
+        if let Some(g) = gradient {
+            Self::check_color_consistency(self.background, g.format())?;
+        }
+
+        self.gradient = gradient;
+
+        Ok(())
+    }
+
+    /// Sets the warning `Color` of the columns that contain clipped samples.
+    ///
+    /// Clipped samples are not highlighted if it is `None`, which is the default.
+    ///
+    /// # Arguments
+    /// * `clipping` - The warning `Color`, format must be consistent with background.
+    pub fn set_clipping(&mut self, clipping: Option<Color>) -> Result<(), Box<Error>> {
+        if let Some(c) = clipping {
+            Self::check_color_consistency(self.background, c)?;
+        }
+
+        self.clipping = clipping;
+
+        Ok(())
+    }
+
+    /// Sets the `Stems` that the samples are drawn as
+    /// when zoomed in past `Stems::threshold`, if the renderer keeps the samples.
+    ///
+    /// Stems are not drawn if it is `None`, which is the default.
+    ///
+    /// # Arguments
+    /// * `stems` - The `Stems`, its `threshold` must be at least 1.
+    pub fn set_stems(&mut self, stems: Option<Stems>) -> Result<(), Box<Error>> {
+        if let Some(s) = stems {
+            if s.threshold.is_nan() || s.threshold < 1f64 {
+                return Err(Box::new(InvalidSizeError {
+                    var_name: "stems".to_string(),
+                }));
+            }
+        }
+
+        self.stems = stems;
+
+        Ok(())
+    }
+
+    /// Returns the `(lower, upper)` limits beyond which (inclusive)
+    /// samples of type `T` are counted as clipped, following `clipping_threshold`.
+    pub(crate) fn clipping_limits<T: Sample + FullScale>(&self) -> (f64, f64) {
+        let (min, max) = T::full_scale();
+        let center = (min + max) / 2f64;
+        (
+            center + (min - center) * self.clipping_threshold,
+            center + (max - center) * self.clipping_threshold,
+        )
+    }
+
+    /// Calculates the heights of the upper and lower edges of the part
+    /// that represents the amplitudes from `min` to `max`,
+    /// measured from the bottom of an image that is `h` pixels high.
+    ///
+    /// The results are not clamped into the image.
+    pub(crate) fn envelope(&self, min: f64, max: f64, h: usize) -> (f64, f64) {
+        let h = h as f64;
+        let full_scale = self.amp_min.abs().max(self.amp_max.abs());
+        match self.display_mode {
+            DisplayMode::MinMax => {
+                let amp_min = self.scale_amplitude(self.amp_min);
+                let scale = 1f64 / (self.scale_amplitude(self.amp_max) - amp_min) * h;
+                ((self.scale_amplitude(max) - amp_min) * scale, (self.scale_amplitude(min) - amp_min) * scale)
+            }
+            DisplayMode::Rectified(baseline) => {
+                let peak = self.scale_amplitude(min.abs().max(max.abs())) / full_scale;
+                match baseline {
+                    Baseline::Bottom => (peak * h, 0f64),
+                    Baseline::Center => ((1f64 + peak) * h / 2f64, (1f64 - peak) * h / 2f64),
+                }
+            }
+        }
+    }
+
+    /// Maps the amplitude `v` by the `AmplitudeScale`.
+    pub(crate) fn scale_amplitude(&self, v: f64) -> f64 {
+        let full_scale = self.amp_min.abs().max(self.amp_max.abs());
+        self.amplitude_scale.apply(v, full_scale)
+    }
 }
 
 impl Default for WaveformConfig {
@@ -105,14 +592,189 @@ impl Default for WaveformConfig {
         Self {
             amp_min: -1f64,
             amp_max: 1f64,
+            amplitude_scale: AmplitudeScale::Linear,
+            orientation: Orientation::Horizontal,
+            display_mode: DisplayMode::MinMax,
+            interpolation: None,
+            bars: None,
+            anti_aliased: false,
+            composition: Composition::Overwrite,
+            clipping_threshold: 1f64,
             foreground: Color::Scalar(255),
             background: Color::Scalar(0),
+            rms: None,
+            gradient: None,
+            clipping: None,
+            stems: None,
         }
     }
 }
 
-/// Time range specifiers used to determine which part of the wave to plot.
+/// Where annotations are drawn relative to the waveform.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Layer {
+    /// Only the background is drawn over, so the waveform stays visible.
+    Behind,
+    /// Drawn over the waveform as well.
+    InFront,
+}
+
+/// A marker such as a cue point or a chapter marker,
+/// which is drawn as a line across the waveform.
+#[derive(Copy, Clone)]
+pub struct Marker {
+    /// The position of the marker.
+    /// Only the beginning of the `TimeRange` is used.
+    pub position: TimeRange,
+    pub color: Color,
+    pub layer: Layer,
+}
+
+/// A region of the waveform, which is shaded with a `Color`.
 #[derive(Copy, Clone)]
+pub struct Region {
+    pub range: TimeRange,
+    pub color: Color,
+    /// How strongly the region is shaded, ranging from 0 to 1.
+    pub opacity: f64,
+    pub layer: Layer,
+}
+
+/// A playhead cursor, a selection, the played part of the waveform
+/// and annotations, which are drawn in the same pass as the waveform
+/// by `render_write_with_overlay`.
+///
+/// * The column that contains the playhead is filled with the cursor `Color`.
+/// * The columns before the playhead are drawn in the progress `Color`
+///   instead of the foreground `Color` (or the `Gradient`).
+/// * The columns within the selection are tinted with the selection `Color`.
+/// * `Region`s are shaded and `Marker`s are drawn as lines,
+///   either behind or in front of the waveform.
+///
+/// The cursor is drawn on top of everything else, followed by the selection,
+/// and the annotations are drawn in the order they are given.
+/// The formats of all the `Color`s must be consistent with those of the `WaveformConfig`.
+#[derive(Clone)]
+pub struct Overlay {
+    /// The position of the playhead.
+    /// Only the beginning of the `TimeRange` is used.
+    pub playhead: Option<TimeRange>,
+    pub selection: Option<TimeRange>,
+    /// How strongly the selection is tinted, ranging from 0 to 1.
+    pub selection_opacity: f64,
+    pub markers: Vec<Marker>,
+    pub regions: Vec<Region>,
+    cursor: Color,
+    progress: Color,
+    selection_color: Color,
+}
+
+impl Overlay {
+    /// The constructor.
+    ///
+    /// Neither the playhead nor the selection is set, and there are no annotations.
+    ///
+    /// # Arguments
+    /// * `cursor` - `Color` of the playhead cursor.
+    /// * `progress` - `Color` of the played part of the waveform.
+    /// * `selection` - `Color` that the selection is tinted with.
+    pub fn new(cursor: Color, progress: Color, selection: Color) -> Result<Self, Box<Error>> {
+        WaveformConfig::check_color_consistency(cursor, progress)?;
+        WaveformConfig::check_color_consistency(cursor, selection)?;
+
+        Ok(Self {
+            playhead: None,
+            selection: None,
+            selection_opacity: 0.5f64,
+            markers: Vec::new(),
+            regions: Vec::new(),
+            cursor,
+            progress,
+            selection_color: selection,
+        })
+    }
+
+    pub fn get_cursor(&self) -> Color {
+        self.cursor
+    }
+    pub fn get_progress(&self) -> Color {
+        self.progress
+    }
+    pub fn get_selection_color(&self) -> Color {
+        self.selection_color
+    }
+
+    /// Checks that the formats of the `Color`s are consistent with `config`.
+    pub(crate) fn check_format(&self, config: &WaveformConfig) -> Result<(), Box<Error>> {
+        let background = config.get_background();
+        WaveformConfig::check_color_consistency(background, self.cursor)?;
+        for marker in self.markers.iter() {
+            WaveformConfig::check_color_consistency(background, marker.color)?;
+        }
+        for region in self.regions.iter() {
+            WaveformConfig::check_color_consistency(background, region.color)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the overlay to the `colors` (background, foreground and RMS)
+    /// of the column that covers the (fractional) samples from `t0` to `t1`.
+    ///
+    /// Returns the `Color` that the whole column should be filled with instead
+    /// of drawing the waveform, if there is one.
+    pub(crate) fn apply(&self, t0: f64, t1: f64, sample_rate: f64, colors: &mut [[u8; 4]; 3]) -> Option<[u8; 4]> {
+        let contains = |position: TimeRange| {
+            let p = position.to_sample_tuple(sample_rate).0 as f64;
+            t0 <= p && p < t1
+        };
+        let overlaps = |range: TimeRange| {
+            let (b, e) = range.to_sample_tuple(sample_rate);
+            t0 < e as f64 && (b as f64) < t1
+        };
+        let tint = |colors: &mut [[u8; 4]], color: Color, opacity: f64| {
+            for c in colors.iter_mut() {
+                *c = mix(c, &color.to_bytes(), opacity);
+            }
+        };
+
+        let mut fill = None;
+        if let Some(playhead) = self.playhead {
+            if contains(playhead) {
+                return Some(self.cursor.to_bytes());
+            }
+            if t1 <= playhead.to_sample_tuple(sample_rate).0 as f64 {
+                colors[1] = self.progress.to_bytes();
+            }
+        }
+
+        for region in self.regions.iter().filter(|r| overlaps(r.range)) {
+            match region.layer {
+                Layer::Behind => tint(&mut colors[..1], region.color, region.opacity),
+                Layer::InFront => tint(&mut colors[..], region.color, region.opacity),
+            }
+        }
+        for marker in self.markers.iter().filter(|m| contains(m.position)) {
+            match marker.layer {
+                Layer::Behind => colors[0] = marker.color.to_bytes(),
+                Layer::InFront => fill = Some(marker.color.to_bytes()),
+            }
+        }
+
+        if let Some(selection) = self.selection {
+            if overlaps(selection) {
+                if let Some(ref mut c) = fill {
+                    *c = mix(c, &self.selection_color.to_bytes(), self.selection_opacity);
+                }
+                tint(&mut colors[..], self.selection_color, self.selection_opacity);
+            }
+        }
+
+        fill
+    }
+}
+
+/// Time range specifiers used to determine which part of the wave to plot.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TimeRange {
     Seconds(f64, f64),
     Samples(usize, usize),
@@ -1,9 +1,12 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::io::{Read, Write};
 
 use super::misc::*;
 use super::error::*;
 use super::binned::BinnedWaveformRenderer;
+use super::full_scale::FullScale;
+use super::cache::{read_levels, write_levels, CacheSample};
 
 /// A renderer that contains multiple `BinnedWaveformRenderer`s
 /// with different bin sizes.
@@ -20,14 +23,18 @@ impl<T: Sample> MultiWaveformRenderer<T> {
     ///
     /// # Arguments
     ///
-    /// * `samples` - The samples that will be used to calculate binned min / max values.
+    /// * `samples` - The samples that will be used to calculate binned min / max values
+    ///               and sums of squares.
     ///               It must also contain the sample rate that is used by
     ///               `BinnedWaveformRenderer` to render images when given a
     ///               `TimeRange::Seconds`.
     /// * `bin_sizes` - The sizes of the bins which the min / max values will be binned
     ///                into.
     /// * `config` - See `WaveformConfig`.
-    pub fn new(samples: &SampleSequence<T>, bin_sizes: &[usize], config: WaveformConfig) -> Result<Self, Box<Error>> {
+    pub fn new(samples: &SampleSequence<T>, bin_sizes: &[usize], config: WaveformConfig) -> Result<Self, Box<Error>>
+    where
+        T: FullScale,
+    {
         let mut r = MultiWaveformRenderer {
             binned: HashMap::new(),
             sample_rate: samples.sample_rate,
@@ -38,17 +45,46 @@ impl<T: Sample> MultiWaveformRenderer<T> {
         }
         bss.sort();
 
-        // TODO: This is obviously improvable if we use the 
-        // result for smaller bin sizes for calculating the
-        // larger bin sizes.
-        for bs in bss.iter() {
-            r.binned
-                .insert(*bs, try!(BinnedWaveformRenderer::new(samples, *bs, config)));
+        // The bins of a bin size that is a multiple of a smaller one
+        // are calculated from the smaller bins instead of the samples.
+        for (i, bs) in bss.iter().enumerate() {
+            let divisor = bss[..i].iter().rev().find(|&&smaller| smaller != 0 && bs % smaller == 0);
+            let binned = match divisor {
+                Some(smaller) => r.binned[smaller].rebin(*bs)?,
+                None => BinnedWaveformRenderer::new(samples, *bs, config)?,
+            };
+            r.binned.insert(*bs, binned);
         }
 
         Ok(r)
     }
 
+    /// Keeps the samples in the `BinnedWaveformRenderer` with the
+    /// smallest bin size, which is the one chosen when zoomed in below one
+    /// sample per pixel.
+    ///
+    /// See `BinnedWaveformRenderer::keep_samples`.
+    pub fn keep_samples(&mut self, samples: Vec<T>) -> Result<(), Box<Error>> {
+        match self.binned.keys().min().cloned() {
+            Some(bin_size) => self.binned.get_mut(&bin_size).unwrap().keep_samples(samples),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+
+    /// Calculates the average number of samples per pixel along the time axis,
+    /// following the `Orientation` of the `BinnedWaveformRenderer`s.
+    fn get_samples_per_pixel(&self, range: TimeRange, shape: (usize, usize)) -> f64 {
+        let (begin, end) = range.to_sample_tuple(self.sample_rate);
+
+        let orientation = match self.binned.values().next() {
+            Some(b) => b.config.orientation,
+            None => Orientation::Horizontal,
+        };
+        let (w, _) = orientation.logical_shape(shape);
+
+        ((end - begin) as f64) / (w as f64)
+    }
+
     fn get_optimal_bin_size(&self, samples_per_pixel: f64) -> Option<usize> {
 
         let mut bin_sizes: Vec<usize> = self.binned.keys().map(|x| *x).collect();
@@ -69,6 +105,14 @@ impl<T: Sample> MultiWaveformRenderer<T> {
         Some(bin_size)
     }
 
+    /// Returns the `BinnedWaveformRenderer` that would be used
+    /// to render the samples within `range` into an image of `shape`.
+    pub fn get_optimal_binned(&self, range: TimeRange, shape: (usize, usize)) -> Option<&BinnedWaveformRenderer<T>> {
+        let samples_per_pixel = self.get_samples_per_pixel(range, shape);
+        self.get_optimal_bin_size(samples_per_pixel)
+            .and_then(|bin_size| self.binned.get(&bin_size))
+    }
+
     /// Renders an image as a `Vec<u8>`.
     ///
     /// `None` will be returned if the area of the specified `shape` is equal to zero.
@@ -83,9 +127,7 @@ impl<T: Sample> MultiWaveformRenderer<T> {
             return None;
         }
 
-        let (begin, end) = range.to_sample_tuple(self.sample_rate);
-
-        let samples_per_pixel = ((end - begin) as f64) / (w as f64);
+        let samples_per_pixel = self.get_samples_per_pixel(range, shape);
 
         if let Some(bin_size) = self.get_optimal_bin_size(samples_per_pixel) {
             return self.binned
@@ -121,9 +163,7 @@ impl<T: Sample> MultiWaveformRenderer<T> {
     /// * `full_shape` - The `(width, height)` of the whole `img` in pixels.
     ///
     pub fn render_write(&mut self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize)) -> Result<(), Box<Error>> {
-        let (begin, end) = range.to_sample_tuple(self.sample_rate);
-
-        let samples_per_pixel = ((end - begin) as f64) / (shape.0 as f64);
+        let samples_per_pixel = self.get_samples_per_pixel(range, shape);
 
         if let Some(bin_size) = self.get_optimal_bin_size(samples_per_pixel) {
             return self.binned
@@ -134,6 +174,96 @@ impl<T: Sample> MultiWaveformRenderer<T> {
             return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()}));
         }
     }
+
+    /// Renders an image with an `Overlay` as a `Vec<u8>`.
+    ///
+    /// See `BinnedWaveformRenderer::render_vec_with_overlay`.
+    pub fn render_vec_with_overlay(&self, range: TimeRange, shape: (usize, usize), overlay: &Overlay) -> Option<Vec<u8>> {
+        self.get_optimal_binned(range, shape)
+            .and_then(|binned| binned.render_vec_with_overlay(range, shape, overlay))
+    }
+
+    /// Writes the image with an `Overlay` into a mutable reference to a slice.
+    ///
+    /// See `BinnedWaveformRenderer::render_write_with_overlay`.
+    pub fn render_write_with_overlay(&self, range: TimeRange, offsets: (usize, usize), shape: (usize, usize), img: &mut [u8], full_shape: (usize, usize), overlay: &Overlay) -> Result<(), Box<Error>> {
+        match self.get_optimal_binned(range, shape) {
+            Some(binned) => binned.render_write_with_overlay(range, offsets, shape, img, full_shape, overlay),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+
+    /// Writes the envelope as an SVG document.
+    ///
+    /// See `BinnedWaveformRenderer::render_svg`.
+    pub fn render_svg<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        match self.get_optimal_binned(range, shape) {
+            Some(binned) => binned.render_svg(range, shape, writer),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+
+    /// Renders an image and writes it into `writer` as a PNG image.
+    ///
+    /// See `BinnedWaveformRenderer::render_png`.
+    #[cfg(feature = "png")]
+    pub fn render_png<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        match self.get_optimal_binned(range, shape) {
+            Some(binned) => binned.render_png(range, shape, writer),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+
+    /// Renders an image and writes it into `writer` in the Netpbm format.
+    ///
+    /// See `BinnedWaveformRenderer::render_pnm`.
+    pub fn render_pnm<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        match self.get_optimal_binned(range, shape) {
+            Some(binned) => binned.render_pnm(range, shape, writer),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+
+    /// Renders an image and writes it into `writer` as a BMP image.
+    ///
+    /// See `BinnedWaveformRenderer::render_bmp`.
+    pub fn render_bmp<W: Write>(&self, range: TimeRange, shape: (usize, usize), writer: &mut W) -> Result<(), Box<Error>> {
+        match self.get_optimal_binned(range, shape) {
+            Some(binned) => binned.render_bmp(range, shape, writer),
+            None => Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        }
+    }
+}
+
+impl<T: CacheSample> MultiWaveformRenderer<T> {
+    /// Writes the bins of every bin size into `writer` in the format of `cache`,
+    /// so that they can be loaded by `read_cache` without the samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `checksum` - A checksum of the samples, such as `cache::checksum`.
+    /// * `writer` - The destination of the cache.
+    pub fn write_cache<W: Write>(&self, checksum: u64, writer: &mut W) -> Result<(), Box<Error>> {
+        let mut bin_sizes: Vec<&usize> = self.binned.keys().collect();
+        bin_sizes.sort();
+        let levels: Vec<&BinnedWaveformRenderer<T>> = bin_sizes.iter().map(|bs| &self.binned[*bs]).collect();
+        write_levels(&levels[..], checksum, writer)
+    }
+
+    /// Loads the bins written by `write_cache`.
+    ///
+    /// See `BinnedWaveformRenderer::read_cache`.
+    pub fn read_cache<R: Read>(reader: &mut R, config: WaveformConfig, checksum: Option<u64>) -> Result<Self, Box<Error>> {
+        let levels = read_levels(reader, config, checksum)?;
+        let sample_rate = match levels.first() {
+            Some(level) => level.get_sample_rate(),
+            None => return Err(Box::new(InvalidSizeError{var_name: "bin sizes".to_string()})),
+        };
+        Ok(Self {
+            binned: levels.into_iter().map(|l| (l.get_bin_size(), l)).collect(),
+            sample_rate,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -162,6 +292,48 @@ mod tests {
 
         mwr.render_vec(TimeRange::Seconds(0f64, 1f64), (1000, 100))
             .unwrap();
+
+        mwr.keep_samples(data.clone()).unwrap();
+        assert!(mwr.binned.get(&10).unwrap().has_samples());
+        assert!(!mwr.binned.get(&100).unwrap().has_samples());
+    }
+
+    #[test]
+    fn vertical() {
+        let data = vec![0f64; 50000];
+        let ss = SampleSequence {
+            data: &data[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.orientation = Orientation::Vertical;
+        let mwr = MultiWaveformRenderer::new(&ss, &[10, 50, 100], config).unwrap();
+
+        // 1000 samples over 100 pixels of height.
+        assert_eq!(mwr.get_samples_per_pixel(TimeRange::Samples(0, 1000), (10, 100)), 10f64);
+    }
+
+    #[test]
+    fn bars() {
+        let data: Vec<f64> = (0..50000).map(|t| ((t as f64) * 0.001f64).sin()).collect();
+        let ss = SampleSequence {
+            data: &data[..],
+            sample_rate: 44100f64,
+        };
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.bars = Some(Bars {
+            width: 3,
+            gap: 2,
+            corner_radius: 1,
+            aggregation: Aggregation::Peak,
+        });
+        let mut mwr = MultiWaveformRenderer::new(&ss, &[10, 100], config).unwrap();
+        let (range, shape) = (TimeRange::Samples(0, 50000), (100, 20));
+
+        let expected = mwr.get_optimal_binned(range, shape).unwrap().render_vec(range, shape).unwrap();
+        assert_eq!(mwr.render_vec(range, shape).unwrap(), expected);
+        // Every fourth and fifth column is a gap.
+        assert!(expected.chunks(100).all(|row| row[3] == 0 && row[4] == 0));
     }
 
     #[test]
@@ -0,0 +1,81 @@
+//! Writers of the Netpbm formats.
+//!
+//! Images are written as PGM, PPM or PAM depending on the `Color` format,
+//! all of which are read by most image tools (e.g. ImageMagick).
+
+use std::error::Error;
+use std::io::Write;
+
+use error::InvalidSizeError;
+
+/// Writes `img` into `writer` in the Netpbm format that suits `bytes_per_pixel`,
+/// which is 1, 3 or 4 as in `Color::bytes_per_pixel`.
+///
+/// * 1 (`Color::Scalar`) - PGM (`P5`).
+/// * 3 (`Color::Vector3`) - PPM (`P6`).
+/// * 4 (`Color::Vector4`) - PAM (`P7`) with the `RGB_ALPHA` tuple type.
+///
+/// It will raise an error if
+///
+/// * the area of `shape` is equal to zero.
+/// * `bytes_per_pixel` is not one of the above.
+/// * the length of `img` is not equal to `shape.0 * shape.1 * bytes_per_pixel`.
+/// * writing into `writer` fails.
+///
+/// # Arguments
+///
+/// * `img` - The pixels laid out as in `render_vec`, or a whole image
+///           that has been written into by `render_write`.
+/// * `shape` - The `(width, height)` of `img` in pixels.
+/// * `bytes_per_pixel` - The number of bytes each pixel occupies.
+/// * `writer` - The destination of the image.
+pub fn write_pnm<W: Write>(img: &[u8], shape: (usize, usize), bytes_per_pixel: usize, writer: &mut W) -> Result<(), Box<Error>> {
+    let (w, h) = shape;
+    if w == 0 || h == 0 {
+        return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
+    }
+    if img.len() != w * h * bytes_per_pixel {
+        return Err(Box::new(InvalidSizeError{var_name: "img".to_string()}));
+    }
+
+    match bytes_per_pixel {
+        1 => write!(writer, "P5\n{} {}\n255\n", w, h)?,
+        3 => write!(writer, "P6\n{} {}\n255\n", w, h)?,
+        4 => write!(
+            writer,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            w,
+            h
+        )?,
+        _ => return Err(Box::new(InvalidSizeError{var_name: "bytes_per_pixel".to_string()})),
+    }
+    writer.write_all(img)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pnm() {
+        let mut v: Vec<u8> = Vec::new();
+        write_pnm(&[0, 255, 128, 1, 2, 3], (3, 2), 1, &mut v).unwrap();
+        assert_eq!(v, b"P5\n3 2\n255\n\x00\xff\x80\x01\x02\x03".to_vec());
+
+        let mut v: Vec<u8> = Vec::new();
+        write_pnm(&[0, 255, 128, 1, 2, 3], (2, 1), 3, &mut v).unwrap();
+        assert_eq!(v, b"P6\n2 1\n255\n\x00\xff\x80\x01\x02\x03".to_vec());
+
+        let mut v: Vec<u8> = Vec::new();
+        write_pnm(&[0, 255, 128, 1], (1, 1), 4, &mut v).unwrap();
+        assert_eq!(
+            v,
+            b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x00\xff\x80\x01".to_vec()
+        );
+
+        assert!(write_pnm(&[0, 255, 128, 1], (2, 1), 2, &mut Vec::new()).is_err());
+        assert!(write_pnm(&[0, 255, 128, 1], (3, 1), 1, &mut Vec::new()).is_err());
+    }
+}
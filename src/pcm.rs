@@ -0,0 +1,359 @@
+//! Decoding of PCM samples stored as bytes.
+//!
+//! Used by the readers of audio files, and to bin headerless PCM data
+//! directly into `BinnedWaveformRenderer`s.
+
+use std::cmp;
+use std::error::Error;
+use std::io::{self, Read};
+
+#[cfg(any(feature = "wav", feature = "aiff"))]
+use error::InvalidDataError;
+use error::InvalidSizeError;
+use misc::*;
+use binned::{Binner, BinnedWaveformRenderer};
+
+/// Sample format specifiers of PCM data.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SampleFormat {
+    /// Unsigned 8-bit integers, centered at 128.
+    U8,
+    /// Signed 8-bit integers.
+    I8,
+    /// Signed 16-bit integers.
+    I16,
+    /// Signed 24-bit integers packed into 3 bytes.
+    I24,
+    /// Signed 32-bit integers.
+    I32,
+    /// 32-bit floating point numbers.
+    F32,
+    /// 64-bit floating point numbers.
+    F64,
+}
+
+/// Byte order specifiers.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// How the samples of multiple channels are laid out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Layout {
+    /// Frames, each of which has a sample of every channel in order.
+    Interleaved,
+    /// The given number of samples of each channel, one channel after another.
+    Planar(usize),
+}
+
+/// The format of headerless PCM data.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PcmFormat {
+    pub sample_format: SampleFormat,
+    pub endianness: Endianness,
+    pub nb_channels: usize,
+    pub layout: Layout,
+    pub sample_rate: f64,
+}
+
+impl SampleFormat {
+    /// The number of bytes a sample occupies.
+    pub fn bytes_per_sample(&self) -> usize {
+        match *self {
+            SampleFormat::U8 | SampleFormat::I8 => 1,
+            SampleFormat::I16 => 2,
+            SampleFormat::I24 => 3,
+            SampleFormat::I32 | SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+
+    /// Decodes the sample in the first `bytes_per_sample()` bytes of `bytes`,
+    /// normalizing integers into `-1..1` by their full scale.
+    ///
+    /// Both the negative and the positive full scale of integers are mapped
+    /// to `-1` and `1` exactly, so that clipped samples are detected at either limit.
+    pub fn decode(&self, bytes: &[u8], endianness: Endianness) -> f32 {
+        // The bytes in the big-endian order, left-aligned.
+        let l = self.bytes_per_sample();
+        let mut b = [0u8; 8];
+        match endianness {
+            Endianness::Big => b[..l].copy_from_slice(&bytes[..l]),
+            Endianness::Little => for (i, v) in bytes[..l].iter().rev().enumerate() {
+                b[i] = *v;
+            },
+        }
+        match *self {
+            SampleFormat::U8 => normalize(b[0] as i32 - 128, i8::MAX as i32),
+            SampleFormat::I8 => normalize(b[0] as i8 as i32, i8::MAX as i32),
+            SampleFormat::I16 => normalize(i16::from_be_bytes([b[0], b[1]]) as i32, i16::MAX as i32),
+            // The 24 bits are placed at the top of an `i32` and shifted back to keep the sign.
+            SampleFormat::I24 => normalize(i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8, 0x7fffff),
+            SampleFormat::I32 => normalize(i32::from_be_bytes([b[0], b[1], b[2], b[3]]), i32::MAX),
+            SampleFormat::F32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            SampleFormat::F64 => f64::from_be_bytes(b) as f32,
+        }
+    }
+}
+
+/// Normalizes the integer `v` whose positive full scale is `max`
+/// and negative full scale is `-max - 1`.
+fn normalize(v: i32, max: i32) -> f32 {
+    if v > 0 {
+        v as f32 / max as f32
+    } else {
+        v as f32 / (max as f32 + 1f32)
+    }
+}
+
+#[cfg(any(feature = "wav", feature = "aiff"))]
+pub(crate) fn invalid(reason: &str) -> Box<Error> {
+    Box::new(InvalidDataError {
+        reason: reason.to_string(),
+    })
+}
+
+/// Fills `buf`, or returns `false` if the end has been reached before
+/// reading anything.
+#[cfg(any(feature = "wav", feature = "aiff"))]
+pub(crate) fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, Box<Error>> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("unexpected end of the file")),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads the whole body of a chunk of `size` bytes.
+#[cfg(any(feature = "wav", feature = "aiff"))]
+pub(crate) fn read_body<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>, Box<Error>> {
+    let mut body = Vec::new();
+    reader.take(size).read_to_end(&mut body)?;
+    if (body.len() as u64) < size {
+        return Err(invalid("unexpected end of the file"));
+    }
+    Ok(body)
+}
+
+#[cfg(any(feature = "wav", feature = "aiff"))]
+pub(crate) fn skip<R: Read>(reader: &mut R, size: u64) -> Result<(), Box<Error>> {
+    io::copy(&mut reader.take(size), &mut io::sink())?;
+    Ok(())
+}
+
+/// The size of the buffer `for_each_frame` reads into, unless a frame is larger.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Reads up to `size` bytes of frames of `block_align` bytes
+/// a block of frames at a time, and passes each of them to `f`.
+///
+/// An incomplete frame at the end is ignored.
+fn for_each_frame<R: Read, F: FnMut(&[u8])>(reader: &mut R, size: u64, block_align: usize, mut f: F) -> Result<(), Box<Error>> {
+    let mut data = reader.take(size);
+    let mut buf = vec![0u8; cmp::max(block_align, BUFFER_SIZE) / block_align * block_align];
+    let mut filled = 0;
+    loop {
+        let n = match data.read(&mut buf[filled..]) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+        if n == 0 {
+            break;
+        }
+        filled += n;
+        let complete = filled / block_align * block_align;
+        for frame in buf[..complete].chunks(block_align) {
+            f(frame);
+        }
+        buf.copy_within(complete..filled, 0);
+        filled -= complete;
+    }
+    Ok(())
+}
+
+/// Reads up to `size` bytes of interleaved frames of `block_align` bytes,
+/// and decodes them into `channels`.
+#[cfg(any(feature = "wav", feature = "aiff"))]
+pub(crate) fn read_frames<R: Read>(reader: &mut R, size: u64, block_align: usize, format: SampleFormat, endianness: Endianness, channels: &mut [Vec<f32>]) -> Result<(), Box<Error>> {
+    let l = format.bytes_per_sample();
+    let nb_frames = (size / block_align as u64) as usize;
+    // The size may be far larger than the actual data.
+    let reserved = nb_frames.min(BUFFER_SIZE * 16 / channels.len().max(1));
+    for c in channels.iter_mut() {
+        c.reserve(reserved);
+    }
+    for_each_frame(reader, size, block_align, |frame| {
+        for (c, channel) in channels.iter_mut().enumerate() {
+            channel.push(format.decode(&frame[c * l..], endianness));
+        }
+    })
+}
+
+/// A `Binner` for each channel, and the number of bytes of a frame
+/// for `Layout::Interleaved`, or of a channel for `Layout::Planar`.
+type Binners = (Vec<Binner<f32>>, usize);
+
+fn binners(format: &PcmFormat, bin_size: usize, config: &WaveformConfig) -> Result<Binners, Box<Error>> {
+    let l = format.sample_format.bytes_per_sample();
+    let size = match format.layout {
+        Layout::Interleaved => l.checked_mul(format.nb_channels),
+        Layout::Planar(n) => l.checked_mul(n),
+    };
+    if bin_size == 0 {
+        return Err(Box::new(InvalidSizeError {
+            var_name: "bin_size".to_string(),
+        }));
+    }
+    match size {
+        Some(size) if size > 0 && format.nb_channels > 0 => {
+            Ok(((0..format.nb_channels).map(|_| Binner::new(bin_size, config)).collect(), size))
+        }
+        _ => Err(Box::new(InvalidSizeError {
+            var_name: "nb_channels and/or layout".to_string(),
+        })),
+    }
+}
+
+/// Bins headerless PCM data into a `BinnedWaveformRenderer` per channel.
+///
+/// The samples are decoded and binned one at a time, so they are never
+/// copied into a `Vec`. Samples that are cut off at the end are ignored.
+///
+/// It will raise an error if `format.nb_channels` is zero, or the size of
+/// a frame (or a channel for `Layout::Planar`) in bytes is zero or overflows,
+/// or any channel has fewer samples than `bin_size`.
+///
+/// # Arguments
+///
+/// * `bytes` - The PCM data.
+/// * `format` - See `PcmFormat`.
+/// * `bin_size` - See `BinnedWaveformRenderer::new`.
+/// * `config` - See `WaveformConfig`, which is used for every channel.
+pub fn bin_slice(bytes: &[u8], format: &PcmFormat, bin_size: usize, config: WaveformConfig) -> Result<Vec<BinnedWaveformRenderer<f32>>, Box<Error>> {
+    let (mut binners, size) = binners(format, bin_size, &config)?;
+    let l = format.sample_format.bytes_per_sample();
+    let decode = |s: &[u8]| format.sample_format.decode(s, format.endianness);
+    match format.layout {
+        Layout::Interleaved => for frame in bytes.chunks_exact(size) {
+            for (c, binner) in binners.iter_mut().enumerate() {
+                binner.push(decode(&frame[c * l..]));
+            }
+        },
+        Layout::Planar(_) => for (plane, binner) in bytes.chunks(size).zip(binners.iter_mut()) {
+            for s in plane.chunks_exact(l) {
+                binner.push(decode(s));
+            }
+        },
+    }
+    binners.into_iter().map(|b| b.finish(format.sample_rate, config)).collect()
+}
+
+/// Reads headerless PCM data from `reader` until the end (or the end of
+/// the last channel for `Layout::Planar`), and bins it like `bin_slice`.
+pub fn bin_reader<R: Read>(reader: &mut R, format: &PcmFormat, bin_size: usize, config: WaveformConfig) -> Result<Vec<BinnedWaveformRenderer<f32>>, Box<Error>> {
+    let (mut binners, size) = binners(format, bin_size, &config)?;
+    let l = format.sample_format.bytes_per_sample();
+    let decode = |s: &[u8]| format.sample_format.decode(s, format.endianness);
+    match format.layout {
+        Layout::Interleaved => for_each_frame(reader, u64::MAX, size, |frame| {
+            for (c, binner) in binners.iter_mut().enumerate() {
+                binner.push(decode(&frame[c * l..]));
+            }
+        })?,
+        Layout::Planar(_) => for binner in binners.iter_mut() {
+            for_each_frame(reader, size as u64, l, |s| binner.push(decode(s)))?;
+        },
+    }
+    binners.into_iter().map(|b| b.finish(format.sample_rate, config)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(SampleFormat::U8.decode(&[0], Endianness::Little), -1f32);
+        assert_eq!(SampleFormat::I8.decode(&[0xc0], Endianness::Little), -0.5f32);
+        assert_eq!(SampleFormat::I16.decode(&[0x00, 0xc0], Endianness::Little), -0.5f32);
+        assert_eq!(SampleFormat::I16.decode(&[0xc0, 0x00], Endianness::Big), -0.5f32);
+        assert_eq!(SampleFormat::I24.decode(&[0x00, 0x00, 0xc0], Endianness::Little), -0.5f32);
+        assert_eq!(SampleFormat::I32.decode(&[0x80, 0, 0, 0], Endianness::Big), -1f32);
+
+        // The positive full scale is mapped to 1 as well.
+        assert_eq!(SampleFormat::U8.decode(&[0xff], Endianness::Little), 1f32);
+        assert_eq!(SampleFormat::I8.decode(&[0x7f], Endianness::Little), 1f32);
+        assert_eq!(SampleFormat::I16.decode(&i16::MAX.to_le_bytes(), Endianness::Little), 1f32);
+        assert_eq!(SampleFormat::I16.decode(&i16::MAX.to_be_bytes(), Endianness::Big), 1f32);
+        assert_eq!(SampleFormat::I24.decode(&[0xff, 0xff, 0x7f], Endianness::Little), 1f32);
+        assert_eq!(SampleFormat::I32.decode(&i32::MAX.to_be_bytes(), Endianness::Big), 1f32);
+        assert_eq!(SampleFormat::I16.decode(&[0x00, 0x40], Endianness::Little), 16384f32 / 32767f32);
+        assert_eq!(SampleFormat::F32.decode(&0.25f32.to_le_bytes(), Endianness::Little), 0.25f32);
+        assert_eq!(SampleFormat::F64.decode(&(-0.75f64).to_be_bytes(), Endianness::Big), -0.75f32);
+    }
+
+    #[test]
+    fn bin() {
+        let mut left: Vec<f32> = Vec::new();
+        let mut right: Vec<f32> = Vec::new();
+        let mut interleaved: Vec<u8> = Vec::new();
+        for t in 0..10000 {
+            let l = ((((t as f32) * 0.01f32).sin() * 32767f32) as i16).to_le_bytes();
+            let r = ((((t as f32) * 0.003f32).cos() * 16000f32) as i16).to_le_bytes();
+            left.push(SampleFormat::I16.decode(&l, Endianness::Little));
+            right.push(SampleFormat::I16.decode(&r, Endianness::Little));
+            interleaved.extend_from_slice(&l);
+            interleaved.extend_from_slice(&r);
+        }
+        let planar: Vec<u8> = interleaved.chunks(2).step_by(2).chain(interleaved.chunks(2).skip(1).step_by(2)).flatten().cloned().collect();
+        // A cut-off sample at the end.
+        interleaved.push(0);
+
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        config.set_rms(Some(Color::Scalar(128))).unwrap();
+        let mut format = PcmFormat {
+            sample_format: SampleFormat::I16,
+            endianness: Endianness::Little,
+            nb_channels: 2,
+            layout: Layout::Interleaved,
+            sample_rate: 44100f64,
+        };
+        let expected: Vec<Option<Vec<u8>>> = [&left, &right].iter()
+            .map(|data| {
+                let ss = SampleSequence {
+                    data: &data[..],
+                    sample_rate: 44100f64,
+                };
+                BinnedWaveformRenderer::new(&ss, 10, config).unwrap().render_vec(TimeRange::Seconds(0f64, 0.2f64), (100, 20))
+            })
+            .collect();
+        let render = |binned: Vec<BinnedWaveformRenderer<f32>>| -> Vec<Option<Vec<u8>>> {
+            binned.iter().map(|b| b.render_vec(TimeRange::Seconds(0f64, 0.2f64), (100, 20))).collect()
+        };
+
+        assert_eq!(render(bin_slice(&interleaved, &format, 10, config).unwrap()), expected);
+        assert_eq!(render(bin_reader(&mut &interleaved[..], &format, 10, config).unwrap()), expected);
+        format.layout = Layout::Planar(10000);
+        assert_eq!(render(bin_slice(&planar, &format, 10, config).unwrap()), expected);
+        assert_eq!(render(bin_reader(&mut &planar[..], &format, 10, config).unwrap()), expected);
+
+        assert!(bin_slice(&planar[..10], &format, 10, config).is_err());
+        assert!(bin_slice(&planar, &format, 0, config).is_err());
+        format.layout = Layout::Planar(usize::MAX);
+        assert!(bin_reader(&mut &planar[..], &format, 10, config).is_err());
+        format.layout = Layout::Interleaved;
+        format.nb_channels = usize::MAX / 2 + 1;
+        assert!(bin_reader(&mut &planar[..], &format, 10, config).is_err());
+        format.nb_channels = 0;
+        assert!(bin_slice(&planar, &format, 10, config).is_err());
+    }
+}
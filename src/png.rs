@@ -0,0 +1,395 @@
+//! A minimal PNG encoder without external dependencies.
+//!
+//! Images are compressed by a greedy LZ77 matcher with the fixed Huffman codes
+//! of DEFLATE, which works well enough for the long runs of flat colors
+//! that waveform images consist of.
+
+use std::error::Error;
+use std::io::Write;
+
+use error::InvalidSizeError;
+
+/// Writes `img` into `writer` as a PNG image.
+///
+/// The color type is grayscale, RGB or RGBA (8 bits per channel)
+/// depending on `bytes_per_pixel`, which is 1, 3 or 4 as in `Color::bytes_per_pixel`.
+///
+/// It will raise an error if
+///
+/// * the area of `shape` is equal to zero.
+/// * `bytes_per_pixel` is not one of the above.
+/// * the length of `img` is not equal to `shape.0 * shape.1 * bytes_per_pixel`.
+/// * writing into `writer` fails.
+///
+/// # Arguments
+///
+/// * `img` - The pixels laid out as in `render_vec`.
+/// * `shape` - The `(width, height)` of `img` in pixels.
+/// * `bytes_per_pixel` - The number of bytes each pixel occupies.
+/// * `writer` - The destination of the PNG image.
+pub fn write_png<W: Write>(img: &[u8], shape: (usize, usize), bytes_per_pixel: usize, writer: &mut W) -> Result<(), Box<Error>> {
+    let (w, h) = shape;
+    if w == 0 || h == 0 || w > 0x7fff_ffff || h > 0x7fff_ffff {
+        return Err(Box::new(InvalidSizeError{var_name: "shape".to_string()}));
+    }
+    let color_type = match bytes_per_pixel {
+        1 => 0u8,
+        3 => 2u8,
+        4 => 6u8,
+        _ => return Err(Box::new(InvalidSizeError{var_name: "bytes_per_pixel".to_string()})),
+    };
+    let row = w * bytes_per_pixel;
+    if img.len() != row * h {
+        return Err(Box::new(InvalidSizeError{var_name: "img".to_string()}));
+    }
+
+    // Every row is preceded by its filter type, which is always 0 (None).
+    let mut raw = Vec::with_capacity((row + 1) * h);
+    for r in img.chunks(row) {
+        raw.push(0u8);
+        raw.extend_from_slice(r);
+    }
+
+    writer.write_all(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a])?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&be32(w as u32));
+    ihdr.extend_from_slice(&be32(h as u32));
+    // Bit depth, color type, compression, filter and interlace methods.
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    let table = crc_table();
+    write_chunk(writer, &table, b"IHDR", &ihdr)?;
+    write_chunk(writer, &table, b"IDAT", &zlib(&raw))?;
+    write_chunk(writer, &table, b"IEND", &[])?;
+
+    Ok(())
+}
+
+fn be32(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+fn write_chunk<W: Write>(writer: &mut W, table: &[u32; 256], kind: &[u8; 4], data: &[u8]) -> Result<(), Box<Error>> {
+    writer.write_all(&be32(data.len() as u32))?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&be32(crc32(table, kind, data)))?;
+    Ok(())
+}
+
+/// The table of the CRCs of all bytes, which is built once per image.
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, t) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *t = c;
+    }
+    table
+}
+
+/// The CRC of the chunk type and data, as defined by the PNG specification.
+fn crc32(table: &[u32; 256], kind: &[u8], data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for b in kind.iter().chain(data.iter()) {
+        crc = table[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffff_ffff
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for v in chunk {
+            a += *v as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Writes bits into bytes starting from the least significant bit,
+/// as DEFLATE does.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `n` bits of `v`, least significant bit first.
+    fn bits(&mut self, v: u32, n: u32) {
+        self.acc |= v << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code of length `n`, most significant bit first.
+    fn code(&mut self, code: u32, n: u32) {
+        let mut reversed = 0;
+        for i in 0..n {
+            reversed |= ((code >> i) & 1) << (n - 1 - i);
+        }
+        self.bits(reversed, n);
+    }
+
+    /// Writes a literal byte or an end-of-block / length symbol (`0..=287`).
+    fn symbol(&mut self, s: u32) {
+        match s {
+            0..=143 => self.code(0x30 + s, 8),
+            144..=255 => self.code(0x190 + s - 144, 9),
+            256..=279 => self.code(s - 256, 7),
+            _ => self.code(0xc0 + s - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+const LENGTH_BASES: [u32; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u32; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+/// Compresses `data` into a zlib stream made of a single DEFLATE block
+/// with the fixed Huffman codes.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: vec![0x78, 0x01],
+        acc: 0,
+        nbits: 0,
+    };
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes).
+    writer.bits(0b011, 3);
+
+    // The last position where each hash of 3 bytes appeared.
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |i: usize| -> usize {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if i + 3 <= data.len() {
+            let hs = hash(i);
+            let candidate = head[hs];
+            head[hs] = i;
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let max = (data.len() - i).min(MAX_MATCH);
+                while length < max && data[candidate + length] == data[i + length] {
+                    length += 1;
+                }
+                distance = i - candidate;
+            }
+        }
+
+        if length < 3 {
+            writer.symbol(data[i] as u32);
+            i += 1;
+            continue;
+        }
+
+        let l = LENGTH_BASES.iter().rposition(|b| *b as usize <= length).unwrap();
+        writer.symbol(257 + l as u32);
+        writer.bits(length as u32 - LENGTH_BASES[l], LENGTH_EXTRA[l]);
+        let d = DISTANCE_BASES.iter().rposition(|b| *b as usize <= distance).unwrap();
+        writer.code(d as u32, 5);
+        writer.bits(distance as u32 - DISTANCE_BASES[d], DISTANCE_EXTRA[d]);
+
+        // The positions inside the match are hashed as well,
+        // so that runs can be continued by later matches.
+        for j in (i + 1)..(i + length) {
+            if j + 3 <= data.len() {
+                head[hash(j)] = j;
+            }
+        }
+        i += length;
+    }
+    writer.symbol(256);
+
+    let mut out = writer.finish();
+    out.extend_from_slice(&be32(adler32(data)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads bits from the least significant bit of each byte.
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn bits(&mut self, n: u32) -> u32 {
+            let mut v = 0;
+            for i in 0..n {
+                v |= (((self.bytes[self.pos / 8] >> (self.pos % 8)) & 1) as u32) << i;
+                self.pos += 1;
+            }
+            v
+        }
+
+        /// Reads a Huffman code of length `n`, most significant bit first,
+        /// after the `code` read so far.
+        fn code(&mut self, mut code: u32, n: u32) -> u32 {
+            for _ in 0..n {
+                code = code << 1 | self.bits(1);
+            }
+            code
+        }
+
+        /// Reads a symbol coded by the fixed Huffman codes.
+        fn symbol(&mut self) -> u32 {
+            let c = self.code(0, 7);
+            if c <= 0x17 {
+                return 256 + c;
+            }
+            let c = self.code(c, 1);
+            match c {
+                0x30..=0xbf => c - 0x30,
+                0xc0..=0xc7 => 280 + c - 0xc0,
+                _ => 144 + self.code(c, 1) - 0x190,
+            }
+        }
+    }
+
+    /// Decompresses a zlib stream of stored and fixed Huffman blocks.
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!((stream[0] as u32 * 256 + stream[1] as u32) % 31, 0);
+        assert_eq!(stream[0] & 0x0f, 8);
+        let mut reader = BitReader {
+            bytes: &stream[2..],
+            pos: 0,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let last = reader.bits(1);
+            match reader.bits(2) {
+                0 => {
+                    reader.pos = reader.pos.div_ceil(8) * 8;
+                    let len = reader.bits(16);
+                    assert_eq!(reader.bits(16), !len & 0xffff);
+                    for _ in 0..len {
+                        let b = reader.bits(8) as u8;
+                        out.push(b);
+                    }
+                }
+                1 => loop {
+                    let s = reader.symbol();
+                    if s < 256 {
+                        out.push(s as u8);
+                        continue;
+                    }
+                    if s == 256 {
+                        break;
+                    }
+                    let l = (s - 257) as usize;
+                    let length = (LENGTH_BASES[l] + reader.bits(LENGTH_EXTRA[l])) as usize;
+                    let d = reader.code(0, 5) as usize;
+                    let distance = (DISTANCE_BASES[d] + reader.bits(DISTANCE_EXTRA[d])) as usize;
+                    for _ in 0..length {
+                        let b = out[out.len() - distance];
+                        out.push(b);
+                    }
+                },
+                _ => panic!("unsupported block type"),
+            }
+            if last == 1 {
+                break;
+            }
+        }
+        let end = 2 + reader.pos.div_ceil(8);
+        assert_eq!(&stream[end..end + 4], &be32(adler32(&out)));
+        out
+    }
+
+    #[test]
+    fn inflate_known_streams() {
+        // "abc" in a stored block and in a fixed Huffman block, as written by zlib.
+        assert_eq!(inflate(&[0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27]), b"abc");
+        assert_eq!(inflate(&[0x78, 0x9c, 0x4b, 0x4c, 0x4a, 0x06, 0x00, 0x02, 0x4d, 0x01, 0x27]), b"abc");
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..70000u32 {
+            // Runs, short repeats and pseudo-random bytes.
+            data.push(match (i / 1000) % 3 {
+                0 => 7,
+                1 => (i % 5) as u8 * 40,
+                _ => (i.wrapping_mul(2_654_435_761) >> 24) as u8,
+            });
+        }
+        for len in [0, 1, 2, 3, 4, 258, 259, 1000, data.len()].iter() {
+            assert_eq!(inflate(&zlib(&data[..*len])), &data[..*len]);
+        }
+    }
+
+    #[test]
+    fn png() {
+        let (w, h) = (100, 50);
+        let img: Vec<u8> = (0..w * h * 3).map(|i| if (i / 3) % w < 30 { 255 } else { 0 }).collect();
+        let mut png: Vec<u8> = Vec::new();
+        write_png(&img[..], (w, h), 3, &mut png).unwrap();
+
+        assert_eq!(&png[0..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..29], &[0, 0, 0, 100, 0, 0, 0, 50, 8, 2, 0, 0, 0]);
+        assert_eq!(&png[37..41], b"IDAT");
+        // The flat image is compressed well.
+        assert!(png.len() < img.len() / 10);
+
+        // The IDAT chunk decodes to the rows of the image, each preceded by the filter type 0.
+        let table = crc_table();
+        let length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        let idat = &png[41..41 + length];
+        assert_eq!(&png[41 + length..45 + length], &be32(crc32(&table, b"IDAT", idat)));
+        let raw = inflate(idat);
+        assert_eq!(raw.len(), (w * 3 + 1) * h);
+        for (r, row) in raw.chunks(w * 3 + 1).enumerate() {
+            assert_eq!(row[0], 0);
+            assert_eq!(&row[1..], &img[r * w * 3..(r + 1) * w * 3]);
+        }
+        // IEND always has the same CRC.
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+        assert!(write_png(&img[..], (w, h), 2, &mut Vec::new()).is_err());
+        assert!(write_png(&img[..], (w, h + 1), 3, &mut Vec::new()).is_err());
+    }
+}
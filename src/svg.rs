@@ -0,0 +1,171 @@
+//! Vector output in the SVG format.
+//!
+//! The envelope is drawn as a filled path that follows the same
+//! per-column min / max values as the raster renderers,
+//! so it can be scaled freely without being re-rendered.
+
+use std::error::Error;
+use std::io::Write;
+
+use misc::*;
+use binned::Column;
+
+/// Writes an SVG document that contains the background and the envelope
+/// of `columns` into `writer`.
+///
+/// `shape` is the `(width, height)` of the document in user units,
+/// and the `Orientation`, the `DisplayMode`, the `AmplitudeScale` and the
+/// background / foreground `Color`s follow `config`.
+pub(crate) fn write_svg<T: Sample, I: Iterator<Item = Column<T>>, W: Write>(
+    config: &WaveformConfig,
+    columns: I,
+    shape: (usize, usize),
+    writer: &mut W,
+) -> Result<(), Box<Error>> {
+    let (width, height) = shape;
+    let (_, h) = config.orientation.logical_shape(shape);
+
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = width,
+        h = height
+    )?;
+    writeln!(
+        writer,
+        "<rect width=\"{}\" height=\"{}\" {}/>",
+        width,
+        height,
+        fill(&config.get_background())
+    )?;
+
+    // Every column becomes a rectangular subpath that is written as soon as
+    // it is known, except that consecutive columns of the same height are merged.
+    let mut opened = false;
+    let mut pending: Option<Span> = None;
+    for (x, column) in columns.enumerate() {
+        let span = match column.bins {
+            Some(_) => {
+                let (top, bottom) = config.envelope(column.min.into(), column.max.into(), h);
+                Some(Span { begin: x, end: x + 1, top, bottom })
+            }
+            None => None,
+        };
+        pending = match (pending, span) {
+            (Some(p), Some(s)) if p.top == s.top && p.bottom == s.bottom => Some(Span { end: s.end, ..p }),
+            (p, s) => {
+                if let Some(p) = p {
+                    write_span(writer, config.orientation, h, &p, &mut opened)?;
+                }
+                s
+            }
+        };
+    }
+    if let Some(p) = pending {
+        write_span(writer, config.orientation, h, &p, &mut opened)?;
+    }
+    if opened {
+        writeln!(writer, "\" {}/>", fill(&config.get_foreground()))?;
+    }
+    writeln!(writer, "</svg>")?;
+
+    Ok(())
+}
+
+/// Columns from `begin` (inclusive) to `end` (exclusive) that span from
+/// `bottom` to `top`, which are measured from the bottom.
+#[derive(Copy, Clone)]
+struct Span {
+    begin: usize,
+    end: usize,
+    top: f64,
+    bottom: f64,
+}
+
+/// Writes `span` as a closed subpath, opening the `path` element first
+/// unless `opened` is `true`.
+fn write_span<W: Write>(writer: &mut W, orientation: Orientation, h: usize, span: &Span, opened: &mut bool) -> Result<(), Box<Error>> {
+    if *opened {
+        write!(writer, " ")?;
+    } else {
+        write!(writer, "<path d=\"")?;
+        *opened = true;
+    }
+    let (x0, x1) = (span.begin as f64, span.end as f64);
+    let corners = [(x0, span.top), (x1, span.top), (x1, span.bottom), (x0, span.bottom)];
+    for (i, &(x, u)) in corners.iter().enumerate() {
+        // Converts a point along the time and amplitude axes into a point in the document.
+        let y = h as f64 - u.max(0f64).min(h as f64);
+        let (px, py) = match orientation {
+            Orientation::Horizontal => (x, y),
+            Orientation::Vertical => (h as f64 - y, x),
+        };
+        write!(writer, "{}{},{}", if i == 0 { "M" } else { " L" }, number(px), number(py))?;
+    }
+    write!(writer, " Z")?;
+    Ok(())
+}
+
+/// Formats `v` with at most 3 decimal places, without trailing zeros.
+fn number(v: f64) -> String {
+    let s = format!("{:.3}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+/// The `fill` attribute (and `fill-opacity` if there is an alpha value) of `color`.
+fn fill(color: &Color) -> String {
+    match *color {
+        Color::Scalar(v) => format!("fill=\"rgb({},{},{})\"", v, v, v),
+        Color::Vector3(r, g, b) => format!("fill=\"rgb({},{},{})\"", r, g, b),
+        Color::Vector4(r, g, b, a) => format!(
+            "fill=\"rgb({},{},{})\" fill-opacity=\"{}\"",
+            r,
+            g,
+            b,
+            number(a as f64 / 255f64)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binned::BinnedWaveformRenderer;
+
+    #[test]
+    fn svg() {
+        let samples = [1f64, 0f64, 1f64, 0f64, -1f64, 0f64, 1f64, 0f64, 1f64, 0f64];
+        let mut config = WaveformConfig::new(-1f64, 1f64, Color::Vector4(255, 0, 0, 128), Color::Vector4(0, 0, 0, 255)).unwrap();
+        let mut wfr = BinnedWaveformRenderer::new(
+            &SampleSequence {
+                data: &samples[..],
+                sample_rate: 44100f64,
+            },
+            2,
+            config,
+        ).unwrap();
+
+        let mut svg: Vec<u8> = Vec::new();
+        wfr.render_svg(TimeRange::Samples(0, 6), (3, 4), &mut svg).unwrap();
+        assert_eq!(
+            String::from_utf8(svg).unwrap(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"3\" height=\"4\" viewBox=\"0 0 3 4\">\n\
+             <rect width=\"3\" height=\"4\" fill=\"rgb(0,0,0)\" fill-opacity=\"1\"/>\n\
+             <path d=\"M0,0 L2,0 L2,2 L0,2 Z M2,2 L3,2 L3,4 L2,4 Z\" fill=\"rgb(255,0,0)\" fill-opacity=\"0.502\"/>\n\
+             </svg>\n"
+        );
+
+        config.orientation = Orientation::Vertical;
+        wfr.config = config;
+        let mut svg: Vec<u8> = Vec::new();
+        wfr.render_svg(TimeRange::Samples(0, 6), (4, 3), &mut svg).unwrap();
+        assert!(String::from_utf8(svg).unwrap().contains("<path d=\"M4,0 L4,2 L2,2 L2,0 Z M2,2 L2,3 L0,3 L0,2 Z\""));
+
+        assert!(wfr.render_svg(TimeRange::Samples(0, 6), (0, 4), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+//! A reader of PCM WAV files, including `WAVE_FORMAT_EXTENSIBLE`,
+//! RF64 for files over 4 GB and the metadata of BWF.
+//!
+//! The samples are decoded into `f32` values normalized into `-1..1`
+//! (see `SampleFormat::decode`), one `Vec` per channel, so that they can be
+//! passed to the renderers as `SampleSequence`s.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use misc::*;
+use pcm::{invalid, read_body, read_frames, read_or_eof, skip, Endianness, SampleFormat};
+
+/// The broadcast audio extension (`bext`) chunk of BWF.
+#[derive(Clone, Debug)]
+pub struct Bext {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// `yyyy:mm:dd`
+    pub origination_date: String,
+    /// `hh:mm:ss`
+    pub origination_time: String,
+    /// The position of the first sample since midnight, in samples.
+    pub time_reference: u64,
+    pub coding_history: String,
+}
+
+/// A cue point, with its label and length from the `LIST adtl` chunk if any.
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub id: u32,
+    pub label: Option<String>,
+    /// The cued samples, which is empty for cue points without a length.
+    pub range: TimeRange,
+}
+
+/// The contents of a WAV file.
+pub struct Wav {
+    pub sample_rate: f64,
+    /// The format the samples were stored in.
+    pub format: SampleFormat,
+    /// The samples of each channel.
+    pub channels: Vec<Vec<f32>>,
+    pub bext: Option<Bext>,
+    pub cues: Vec<Cue>,
+}
+
+impl Wav {
+    /// Reads a WAV file from `reader`.
+    ///
+    /// It will raise an error if the data is not a WAV file with PCM
+    /// (8, 16, 24 or 32-bit integer) or IEEE float (32 or 64-bit) samples,
+    /// or reading from `reader` fails.
+    /// A `data` chunk that is cut off is read as far as it goes.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Box<Error>> {
+        let mut riff = [0u8; 12];
+        reader.read_exact(&mut riff)?;
+        let rf64 = match &riff[0..4] {
+            b"RIFF" => false,
+            b"RF64" | b"BW64" => true,
+            _ => return Err(invalid("not a RIFF file")),
+        };
+        if &riff[8..12] != b"WAVE" {
+            return Err(invalid("not a WAVE file"));
+        }
+
+        let mut wav = Wav {
+            sample_rate: 0f64,
+            format: SampleFormat::I16,
+            channels: Vec::new(),
+            bext: None,
+            cues: Vec::new(),
+        };
+        let mut data_size: Option<u64> = None;
+        // The number of bytes per frame from the `fmt ` chunk.
+        let mut frame: Option<usize> = None;
+        let mut cue_points: Vec<(u32, usize)> = Vec::new();
+        let mut labels: Vec<(u32, String)> = Vec::new();
+        let mut lengths: Vec<(u32, usize)> = Vec::new();
+
+        loop {
+            let mut header = [0u8; 8];
+            if !read_or_eof(reader, &mut header)? {
+                break;
+            }
+            let mut size = le32(&header[4..]) as u64;
+            match &header[0..4] {
+                b"ds64" => {
+                    let body = read_body(reader, size)?;
+                    if body.len() < 16 {
+                        return Err(invalid("the ds64 chunk is too short"));
+                    }
+                    data_size = Some(le32(&body[8..]) as u64 | (le32(&body[12..]) as u64) << 32);
+                }
+                b"fmt " => {
+                    let body = read_body(reader, size)?;
+                    let (format, nb_channels, sample_rate, block_align) = parse_fmt(&body)?;
+                    wav.format = format;
+                    wav.sample_rate = sample_rate as f64;
+                    wav.channels = vec![Vec::new(); nb_channels];
+                    frame = Some(block_align);
+                }
+                b"data" => {
+                    if rf64 && size == 0xffff_ffff {
+                        size = match data_size {
+                            Some(size) => size,
+                            None => return Err(invalid("the ds64 chunk is missing")),
+                        };
+                    }
+                    match frame {
+                        Some(block_align) => read_frames(reader, size, block_align, wav.format, Endianness::Little, &mut wav.channels)?,
+                        None => return Err(invalid("the fmt chunk is missing before the data chunk")),
+                    }
+                }
+                b"bext" => wav.bext = Some(parse_bext(&read_body(reader, size)?)?),
+                b"cue " => {
+                    let body = read_body(reader, size)?;
+                    if body.len() >= 4 {
+                        let nb_points = le32(&body) as usize;
+                        for point in body[4..].chunks(24).take(nb_points) {
+                            if point.len() == 24 {
+                                cue_points.push((le32(point), le32(&point[20..]) as usize));
+                            }
+                        }
+                    }
+                }
+                b"LIST" => {
+                    let body = read_body(reader, size)?;
+                    if body.len() >= 4 && &body[0..4] == b"adtl" {
+                        parse_adtl(&body[4..], &mut labels, &mut lengths);
+                    }
+                }
+                _ => skip(reader, size)?,
+            }
+            // Chunks are padded to even sizes.
+            if size % 2 == 1 {
+                read_or_eof(reader, &mut [0u8; 1])?;
+            }
+        }
+
+        if frame.is_none() {
+            return Err(invalid("the fmt chunk is missing"));
+        }
+        wav.cues = cue_points.iter()
+            .map(|&(id, offset)| Cue {
+                id,
+                label: labels.iter().find(|l| l.0 == id).map(|l| l.1.clone()),
+                range: TimeRange::Samples(
+                    offset,
+                    offset + lengths.iter().find(|l| l.0 == id).map_or(0, |l| l.1),
+                ),
+            })
+            .collect();
+
+        Ok(wav)
+    }
+
+    /// Reads a WAV file at `path`. See `read`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<Error>> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// The samples of the `channel`-th channel with the sample rate,
+    /// or `None` if there is no such channel.
+    pub fn sample_sequence(&self, channel: usize) -> Option<SampleSequence<'_, f32>> {
+        self.channels.get(channel).map(|data| SampleSequence {
+            data: &data[..],
+            sample_rate: self.sample_rate,
+        })
+    }
+}
+
+fn le16(b: &[u8]) -> u16 {
+    b[0] as u16 | (b[1] as u16) << 8
+}
+
+fn le32(b: &[u8]) -> u32 {
+    le16(b) as u32 | (le16(&b[2..]) as u32) << 16
+}
+
+/// Returns the sample format, the number of channels, the sample rate and
+/// the number of bytes per frame.
+fn parse_fmt(body: &[u8]) -> Result<(SampleFormat, usize, u32, usize), Box<Error>> {
+    if body.len() < 16 {
+        return Err(invalid("the fmt chunk is too short"));
+    }
+    let mut tag = le16(body);
+    let nb_channels = le16(&body[2..]) as usize;
+    let sample_rate = le32(&body[4..]);
+    let block_align = le16(&body[12..]) as usize;
+    let bits = le16(&body[14..]);
+    if tag == 0xfffe {
+        // WAVE_FORMAT_EXTENSIBLE, where the format tag is at the beginning of the sub format GUID.
+        if body.len() < 26 {
+            return Err(invalid("the fmt chunk is too short"));
+        }
+        tag = le16(&body[24..]);
+    }
+    let format = match (tag, bits) {
+        (1, 8) => SampleFormat::U8,
+        (1, 16) => SampleFormat::I16,
+        (1, 24) => SampleFormat::I24,
+        (1, 32) => SampleFormat::I32,
+        (3, 32) => SampleFormat::F32,
+        (3, 64) => SampleFormat::F64,
+        _ => return Err(invalid("unsupported sample format")),
+    };
+    if nb_channels == 0 || sample_rate == 0 || block_align < nb_channels * format.bytes_per_sample() {
+        return Err(invalid("invalid fmt chunk"));
+    }
+    Ok((format, nb_channels, sample_rate, block_align))
+}
+
+/// Reads a null-padded string.
+fn text(b: &[u8]) -> String {
+    let end = b.iter().position(|c| *c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+fn parse_bext(body: &[u8]) -> Result<Bext, Box<Error>> {
+    // The fixed part ends with the version, the UMID, the loudness values and the reserved bytes.
+    if body.len() < 602 {
+        return Err(invalid("the bext chunk is too short"));
+    }
+    Ok(Bext {
+        description: text(&body[0..256]),
+        originator: text(&body[256..288]),
+        originator_reference: text(&body[288..320]),
+        origination_date: text(&body[320..330]),
+        origination_time: text(&body[330..338]),
+        time_reference: le32(&body[338..]) as u64 | (le32(&body[342..]) as u64) << 32,
+        coding_history: text(&body[602..]),
+    })
+}
+
+/// Collects the labels (`labl`) and the lengths (`ltxt`) of cue points.
+fn parse_adtl(mut body: &[u8], labels: &mut Vec<(u32, String)>, lengths: &mut Vec<(u32, usize)>) {
+    while body.len() >= 8 {
+        let size = le32(&body[4..]) as usize;
+        let sub = &body[8..body.len().min(8 + size)];
+        if sub.len() >= 4 {
+            let id = le32(sub);
+            match &body[0..4] {
+                b"labl" => labels.push((id, text(&sub[4..]))),
+                b"ltxt" if sub.len() >= 8 => lengths.push((id, le32(&sub[4..]) as usize)),
+                _ => {}
+            }
+        }
+        body = &body[body.len().min(8 + size + size % 2)..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::binned::BinnedWaveformRenderer;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        c.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    }
+
+    fn fmt(tag: u16, nb_channels: u16, bits: u16) -> Vec<u8> {
+        let block_align = nb_channels * bits / 8;
+        let mut f = Vec::new();
+        f.extend_from_slice(&tag.to_le_bytes());
+        f.extend_from_slice(&nb_channels.to_le_bytes());
+        f.extend_from_slice(&48000u32.to_le_bytes());
+        f.extend_from_slice(&(48000u32 * block_align as u32).to_le_bytes());
+        f.extend_from_slice(&block_align.to_le_bytes());
+        f.extend_from_slice(&bits.to_le_bytes());
+        f
+    }
+
+    fn riff(id: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut w = id.to_vec();
+        w.extend_from_slice(&((body.len() + 4) as u32).to_le_bytes());
+        w.extend_from_slice(b"WAVE");
+        w.extend_from_slice(&body);
+        w
+    }
+
+    #[test]
+    fn wav() {
+        // Stereo 16-bit with cue points and BWF metadata.
+        let samples: Vec<i16> = vec![0, i16::MAX, -16384, i16::MIN, -8192, 0];
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes().to_vec()).collect();
+        let mut cue = 2u32.to_le_bytes().to_vec();
+        for &(id, offset) in [(1u32, 1u32), (2, 2)].iter() {
+            cue.extend_from_slice(&id.to_le_bytes());
+            cue.extend_from_slice(&[0u8; 16]);
+            cue.extend_from_slice(&offset.to_le_bytes());
+        }
+        let mut adtl = b"adtl".to_vec();
+        adtl.extend(chunk(b"labl", &[&1u32.to_le_bytes()[..], b"Intro\0"].concat()));
+        adtl.extend(chunk(b"ltxt", &[&1u32.to_le_bytes()[..], &2u32.to_le_bytes()[..], &[0u8; 12][..]].concat()));
+        let mut bext = vec![0u8; 602];
+        bext[0..5].copy_from_slice(b"Field");
+        bext[338..342].copy_from_slice(&48000u32.to_le_bytes());
+        bext.extend_from_slice(b"A=PCM\r\n");
+        let file = riff(b"RIFF", &[
+            chunk(b"fmt ", &fmt(1, 2, 16)),
+            chunk(b"bext", &bext),
+            chunk(b"data", &data),
+            chunk(b"cue ", &cue),
+            chunk(b"LIST", &adtl),
+        ]);
+
+        let wav = Wav::read(&mut &file[..]).unwrap();
+        assert_eq!(wav.sample_rate, 48000f64);
+        assert_eq!(wav.format, SampleFormat::I16);
+        assert_eq!(wav.channels, vec![vec![0f32, -0.5f32, -0.25f32], vec![1f32, -1f32, 0f32]]);
+        assert_eq!(wav.sample_sequence(1).unwrap().data.len(), 3);
+
+        // Samples at both limits of `i16` are counted as clipped.
+        let config = WaveformConfig::new(-1f64, 1f64, Color::Scalar(255), Color::Scalar(0)).unwrap();
+        let count_clipped = |ch: usize| {
+            BinnedWaveformRenderer::new(&wav.sample_sequence(ch).unwrap(), 1, config)
+                .unwrap()
+                .count_clipped(TimeRange::Samples(0, 3))
+        };
+        assert_eq!(count_clipped(0), 0);
+        assert_eq!(count_clipped(1), 2);
+        assert!(wav.sample_sequence(2).is_none());
+        let bext = wav.bext.unwrap();
+        assert_eq!(bext.description, "Field");
+        assert_eq!(bext.time_reference, 48000);
+        assert_eq!(bext.coding_history, "A=PCM\r\n");
+        assert_eq!(wav.cues.len(), 2);
+        assert_eq!(wav.cues[0].label, Some("Intro".to_string()));
+        assert_eq!(wav.cues[0].range, TimeRange::Samples(1, 3));
+        assert_eq!(wav.cues[1].range, TimeRange::Samples(2, 2));
+
+        // Mono 24-bit in WAVE_FORMAT_EXTENSIBLE.
+        let mut f = fmt(0xfffe, 1, 24);
+        f.extend_from_slice(&[22, 0, 24, 0, 4, 0, 0, 0, 1, 0]);
+        f.extend_from_slice(&[0u8; 14]);
+        let file = riff(b"RIFF", &[chunk(b"fmt ", &f), chunk(b"data", &[0xff, 0xff, 0x7f, 0, 0, 0x80, 0xff])]);
+        let wav = Wav::read(&mut &file[..]).unwrap();
+        assert_eq!(wav.format, SampleFormat::I24);
+        // The incomplete frame at the end is ignored.
+        assert_eq!(wav.channels, vec![vec![1f32, -1f32]]);
+
+        // Mono 32-bit float in RF64.
+        let mut ds64 = 0u64.to_le_bytes().to_vec();
+        ds64.extend_from_slice(&8u64.to_le_bytes());
+        ds64.extend_from_slice(&2u64.to_le_bytes());
+        ds64.extend_from_slice(&0u32.to_le_bytes());
+        let mut data = chunk(b"data", &[&0.5f32.to_le_bytes()[..], &(-0.25f32).to_le_bytes()[..]].concat());
+        data[4..8].copy_from_slice(&[0xff; 4]);
+        let file = riff(b"RF64", &[chunk(b"ds64", &ds64), chunk(b"fmt ", &fmt(3, 1, 32)), data]);
+        let wav = Wav::read(&mut &file[..]).unwrap();
+        assert_eq!(wav.format, SampleFormat::F32);
+        assert_eq!(wav.channels, vec![vec![0.5f32, -0.25f32]]);
+
+        assert!(Wav::read(&mut &riff(b"RIFF", &[chunk(b"fmt ", &fmt(2, 1, 4))])[..]).is_err());
+        assert!(Wav::read(&mut &riff(b"RIFF", &[chunk(b"data", &[0, 0])])[..]).is_err());
+        assert!(Wav::read(&mut &b"RIFX\0\0\0\0WAVE"[..]).is_err());
+
+        // Truncated cue and LIST chunks are ignored.
+        let file = riff(b"RIFF", &[chunk(b"fmt ", &fmt(1, 1, 16)), chunk(b"cue ", &[]), chunk(b"cue ", &[1, 0]), chunk(b"LIST", &[0])]);
+        assert!(Wav::read(&mut &file[..]).unwrap().cues.is_empty());
+    }
+}